@@ -0,0 +1,333 @@
+//! Chat reporting for `/analytics`: per-day volumes in a given time zone, plus
+//! visitors, response times, busiest hours and top pages over the range.
+//!
+//! Days that are over are computed once per time zone and kept in memory, so
+//! messages imported into them later only show after a restart. The cache
+//! holds chat and visitor IDs, so erasures and retention deletes empty it.
+//! Responses are paired within a day; one answered after local midnight isn't
+//! counted.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    app::AppResult,
+    constants::{COLLECTION, SITE_CHAT_MESSAGE_CATEGORY},
+    qdrant::{keyword_index, qdrant_path, qdrant_post},
+    store::MESSAGE_CATEGORY,
+    transcript::{date_range, timestamp},
+};
+
+/// Longest range one request may cover.
+pub const MAX_DAYS: i64 = 366;
+/// Most pages one request may list.
+pub const MAX_PAGES: usize = 100;
+const PAGE: usize = 256;
+
+/// Finished days by time zone and local date.
+type Cache = HashMap<(Tz, NaiveDate), Arc<Day>>;
+
+static DAYS: Lazy<Mutex<Cache>> = Lazy::new(Default::default);
+
+/// Empties the day cache, after points were deleted or backdated ones
+/// written.
+pub fn forget() {
+    DAYS.lock().unwrap().clear();
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Options {
+    pub from: NaiveDate,
+    /// Last day included.
+    pub to: NaiveDate,
+    /// IANA time zone the days are cut in.
+    #[serde(default = "default_tz")]
+    pub tz: String,
+    /// Pages listed, at most `MAX_PAGES`.
+    #[serde(default = "default_top")]
+    pub top: usize,
+}
+
+fn default_tz() -> String {
+    "UTC".to_string()
+}
+
+fn default_top() -> usize {
+    10
+}
+
+impl Options {
+    /// The time zone, or what's wrong with the request.
+    pub fn check(&self) -> Result<Tz, String> {
+        let tz = self
+            .tz
+            .parse::<Tz>()
+            .map_err(|_| format!("unknown time zone `{}`", self.tz))?;
+        let days = (self.to - self.from).num_days() + 1;
+        if days < 1 {
+            return Err("`to` is before `from`".to_string());
+        }
+        if days > MAX_DAYS {
+            return Err(format!("at most {} days at a time", MAX_DAYS));
+        }
+        Ok(tz)
+    }
+}
+
+/// One local day, as cached.
+struct Day {
+    date: NaiveDate,
+    messages: u64,
+    chats: HashSet<String>,
+    new_chats: usize,
+    /// `v`, or the (pseudonymized) address for messages without one.
+    visitors: HashSet<String>,
+    hours: [u64; 24],
+    response_ms: i64,
+    responses: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DayReport {
+    pub date: NaiveDate,
+    pub new_conversations: usize,
+    pub conversations: usize,
+    pub messages: u64,
+    pub messages_per_conversation: f64,
+    pub visitors: usize,
+    /// Mean time from a visitor message to the next answer in its chat.
+    pub avg_response_seconds: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Hour {
+    /// Local hour, 0–23.
+    pub hour: u32,
+    pub messages: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Page {
+    pub page: String,
+    pub messages: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub tz: String,
+    pub days: Vec<DayReport>,
+    /// The whole range; a chat spanning days counts once here.
+    pub total: DayReport,
+    /// Busiest first.
+    pub hours: Vec<Hour>,
+    pub pages: Vec<Page>,
+}
+
+fn ratio(a: f64, b: f64) -> Option<f64> {
+    (b > 0.0).then(|| a / b)
+}
+
+fn messages_filter(from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Vec<Value> {
+    vec![
+        json!({"key": "c", "match": {"any": [MESSAGE_CATEGORY, SITE_CHAT_MESSAGE_CATEGORY]}}),
+        date_range(from, to),
+    ]
+}
+
+/// Local midnight starting `date`, or the first instant after it when a DST
+/// change skips midnight.
+fn start_of(tz: Tz, date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_time(NaiveTime::MIN);
+    (0..=2)
+        .find_map(|h| {
+            tz.from_local_datetime(&(midnight + TimeDelta::hours(h)))
+                .earliest()
+        })
+        .map_or_else(|| midnight.and_utc(), |t| t.with_timezone(&Utc))
+}
+
+async fn day(tz: Tz, date: NaiveDate) -> AppResult<Arc<Day>> {
+    if let Some(d) = DAYS.lock().unwrap().get(&(tz, date)) {
+        return Ok(d.clone());
+    }
+    let (start, end) = (start_of(tz, date), start_of(tz, date + TimeDelta::days(1)));
+    let messages = qdrant_post(
+        &qdrant_path(&format!("collections/{}/points/count", COLLECTION)).await?,
+        json!({"filter": {"must": messages_filter(Some(start), Some(end))}, "exact": true}),
+    )
+    .await?["result"]["count"]
+        .as_u64()
+        .unwrap_or_default();
+
+    let mut by_chat: HashMap<String, Vec<(DateTime<Utc>, bool)>> = HashMap::new();
+    let mut visitors = HashSet::new();
+    let mut hours = [0; 24];
+    let mut offset = Value::Null;
+    loop {
+        let mut body = json!({
+            "limit": PAGE,
+            "with_payload": ["i", "u", "d", "v", "a"],
+            "filter": {"must": messages_filter(Some(start), Some(end))},
+        });
+        if !offset.is_null() {
+            body["offset"] = offset;
+        }
+        let res = qdrant_post(
+            &qdrant_path(&format!("collections/{}/points/scroll", COLLECTION)).await?,
+            body,
+        )
+        .await?;
+        for p in res["result"]["points"].as_array().into_iter().flatten() {
+            let payload = &p["payload"];
+            let (Some(chat), Some(time)) = (payload["i"].as_str(), timestamp(&payload["d"])) else {
+                continue;
+            };
+            hours[time.with_timezone(&tz).hour() as usize] += 1;
+            if let Some(v) = payload["v"].as_str().or(payload["a"].as_str()) {
+                visitors.insert(v.to_string());
+            }
+            by_chat
+                .entry(chat.to_string())
+                .or_default()
+                .push((time, payload["u"].as_i64() == Some(1)));
+        }
+        offset = res["result"]["next_page_offset"].clone();
+        if offset.is_null() {
+            break;
+        }
+    }
+
+    let (mut response_ms, mut responses) = (0, 0);
+    for messages in by_chat.values_mut() {
+        // a question sorts before an answer with the same time
+        messages.sort_by_key(|(time, user)| (*time, !user));
+        let mut waiting = vec![];
+        for (time, user) in messages.iter() {
+            if *user {
+                waiting.push(*time);
+            } else {
+                for asked in waiting.drain(..) {
+                    response_ms += (*time - asked).num_milliseconds();
+                    responses += 1;
+                }
+            }
+        }
+    }
+
+    let chats: HashSet<String> = by_chat.into_keys().collect();
+    let new_chats = if chats.is_empty() {
+        0
+    } else {
+        let mut before = messages_filter(None, Some(start));
+        before.push(json!({"key": "i", "match": {"any": chats}}));
+        let older = qdrant_post(
+            &qdrant_path(&format!("collections/{}/facet", COLLECTION)).await?,
+            json!({"key": "i", "limit": chats.len(), "filter": {"must": before}, "exact": true}),
+        )
+        .await?["result"]["hits"]
+            .as_array()
+            .map_or(0, Vec::len);
+        chats.len().saturating_sub(older)
+    };
+
+    let day = Arc::new(Day {
+        date,
+        messages,
+        chats,
+        new_chats,
+        visitors,
+        hours,
+        response_ms,
+        responses,
+    });
+    if end <= Utc::now() {
+        DAYS.lock().unwrap().insert((tz, date), day.clone());
+    }
+    Ok(day)
+}
+
+async fn pages(tz: Tz, o: &Options) -> AppResult<Vec<Page>> {
+    let (start, end) = (
+        start_of(tz, o.from),
+        start_of(tz, o.to + TimeDelta::days(1)),
+    );
+    let res = qdrant_post(
+        &qdrant_path(&format!("collections/{}/facet", COLLECTION)).await?,
+        json!({
+            "key": "p",
+            "limit": o.top.clamp(1, MAX_PAGES),
+            "filter": {"must": messages_filter(Some(start), Some(end))},
+            "exact": true,
+        }),
+    )
+    .await?;
+    Ok(res["result"]["hits"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|h| {
+            Some(Page {
+                page: h["value"].as_str()?.to_string(),
+                messages: h["count"].as_u64()?,
+            })
+        })
+        .collect())
+}
+
+fn report(date: NaiveDate, days: &[Arc<Day>]) -> DayReport {
+    let chats: HashSet<&String> = days.iter().flat_map(|d| &d.chats).collect();
+    let visitors: HashSet<&String> = days.iter().flat_map(|d| &d.visitors).collect();
+    let messages = days.iter().map(|d| d.messages).sum::<u64>();
+    DayReport {
+        date,
+        new_conversations: days.iter().map(|d| d.new_chats).sum(),
+        conversations: chats.len(),
+        messages,
+        messages_per_conversation: ratio(messages as f64, chats.len() as f64).unwrap_or_default(),
+        visitors: visitors.len(),
+        avg_response_seconds: ratio(
+            days.iter().map(|d| d.response_ms).sum::<i64>() as f64 / 1000.0,
+            days.iter().map(|d| d.responses).sum::<u64>() as f64,
+        ),
+    }
+}
+
+/// Reports on the days `o` selects, cut in time zone `tz`.
+pub async fn run(tz: Tz, o: &Options) -> AppResult<Report> {
+    // facets count through these
+    keyword_index(COLLECTION, "i").await?;
+    keyword_index(COLLECTION, "p").await?;
+    let mut days = vec![];
+    for date in o.from.iter_days().take_while(|d| *d <= o.to) {
+        days.push(day(tz, date).await?);
+    }
+    let mut hours: Vec<Hour> = (0..24)
+        .map(|h| Hour {
+            hour: h,
+            messages: days.iter().map(|d| d.hours[h as usize]).sum(),
+        })
+        .collect();
+    hours.sort_by_key(|h| (std::cmp::Reverse(h.messages), h.hour));
+    Ok(Report {
+        from: o.from,
+        to: o.to,
+        tz: tz.name().to_string(),
+        days: days
+            .iter()
+            .map(|d| report(d.date, std::slice::from_ref(d)))
+            .collect(),
+        total: report(o.from, &days),
+        hours,
+        pages: pages(tz, o).await?,
+    })
+}
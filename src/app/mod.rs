@@ -15,7 +15,7 @@ impl std::fmt::Display for AppError {
 impl AppError {
     pub fn new(m: &str, e: impl std::error::Error) -> Self {
         AppError {
-            t: format!("{}: {}", m, e.to_string()),
+            t: format!("{}: {}", m, e),
         }
     }
 
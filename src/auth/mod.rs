@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use warp::{Filter, Rejection};
+
+use crate::{constants::SECRETS, privacy::hmac_hex};
+
+/// Rejection for requests without valid admin credentials.
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Compares in constant time so the key can't be guessed byte by byte.
+pub fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Pulls the token out of `Authorization: Bearer <token>`.
+fn bearer(header: Option<String>) -> Option<String> {
+    header?
+        .strip_prefix("Bearer ")
+        .map(|t| t.trim().to_string())
+}
+
+async fn admin_key() -> Option<String> {
+    SECRETS
+        .lock()
+        .await
+        .get("ADMIN_KEY")
+        .filter(|k| !k.is_empty())
+}
+
+/// Whether `token` is the `ADMIN_KEY` secret.
+pub async fn is_admin_key(token: &str) -> bool {
+    match admin_key().await {
+        Some(key) => same(key.as_bytes(), token.as_bytes()),
+        None => false,
+    }
+}
+
+async fn check_admin(header: Option<String>) -> Result<(), Rejection> {
+    match bearer(header) {
+        Some(token) if is_admin_key(&token).await => Ok(()),
+        _ => Err(warp::reject::custom(Unauthorized)),
+    }
+}
+
+/// Lets the request through only with `Authorization: Bearer <ADMIN_KEY>`.
+/// Without an `ADMIN_KEY` secret every admin route stays locked.
+pub fn admin() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(check_admin)
+        .untuple_one()
+}
+
+/// Like `admin`, but also accepts the key as an `access_token` query parameter,
+/// since browsers can't set headers when opening a WebSocket.
+pub fn operator() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(|header: Option<String>, query: HashMap<String, String>| {
+            check_admin(
+                header.or_else(|| query.get("access_token").map(|t| format!("Bearer {}", t))),
+            )
+        })
+        .untuple_one()
+}
+
+/// Cookie the admin console keeps its session in: an expiry time signed with
+/// `ADMIN_KEY`, so the key itself never sits in the browser and changing it
+/// ends every session.
+pub const SESSION_COOKIE: &str = "admin_session";
+/// Seconds a console session lasts.
+pub const SESSION_TTL: i64 = 60 * 60 * 12;
+
+fn session_mac(key: &str, expires: i64) -> String {
+    hmac_hex(key, &format!("admin-session:{}", expires))
+}
+
+/// A fresh console session token, or `None` without an `ADMIN_KEY`.
+pub async fn session_token() -> Option<String> {
+    let key = admin_key().await?;
+    let expires = Utc::now().timestamp() + SESSION_TTL;
+    Some(format!("{}.{}", expires, session_mac(&key, expires)))
+}
+
+async fn valid_session(token: &str) -> bool {
+    let Some(key) = admin_key().await else {
+        return false;
+    };
+    let Some((expires, mac)) = token
+        .split_once('.')
+        .and_then(|(e, mac)| Some((e.parse::<i64>().ok()?, mac)))
+    else {
+        return false;
+    };
+    expires > Utc::now().timestamp() && same(session_mac(&key, expires).as_bytes(), mac.as_bytes())
+}
+
+async fn signed_in_with(header: Option<String>, cookie: Option<String>) -> Result<bool, Rejection> {
+    Ok(match (bearer(header), cookie) {
+        (Some(token), _) => is_admin_key(&token).await,
+        (None, Some(session)) => valid_session(&session).await,
+        (None, None) => false,
+    })
+}
+
+/// Extracts whether the request is from an admin, by bearer header or console
+/// session cookie. Unlike `admin` it never rejects, so console pages can show
+/// the sign-in form instead.
+pub fn signed_in() -> impl Filter<Extract = (bool,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::cookie::optional::<String>(SESSION_COOKIE))
+        .and_then(signed_in_with)
+}
+
+/// Turns rejections the app raises itself into proper status codes.
+pub async fn recover(r: Rejection) -> Result<impl warp::Reply, Rejection> {
+    if r.find::<Unauthorized>().is_some() {
+        return Ok(warp::reply::with_status(
+            "Unauthorized".to_string(),
+            warp::http::StatusCode::UNAUTHORIZED,
+        ));
+    }
+    if r.find::<crate::ratelimit::TooManyRequests>().is_some() {
+        return Ok(warp::reply::with_status(
+            "Too many requests".to_string(),
+            warp::http::StatusCode::TOO_MANY_REQUESTS,
+        ));
+    }
+    if let Some(e) = r.find::<crate::app::AppError>() {
+        tracing::error!("{:#?}", e);
+        return Ok(warp::reply::with_status(
+            "An error occured on our side".to_string(),
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        ));
+    }
+    Err(r)
+}
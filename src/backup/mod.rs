@@ -0,0 +1,179 @@
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    app::AppResult,
+    constants::COLLECTION,
+    qdrant::{qdrant_path, qdrant_post, qdrant_put},
+    util::embeddings,
+};
+
+/// Points fetched per scroll page on export and upserted per batch on import.
+pub const BATCH: usize = 256;
+
+/// One line of a backup: a point as Qdrant stores it. `vector` is left out of
+/// exports unless asked for, and is recomputed on import when missing.
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    pub id: Value,
+    #[serde(default)]
+    pub payload: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector: Option<Value>,
+}
+
+/// Streams every point of the collection as NDJSON, one scroll page per chunk.
+pub fn export(with_vectors: bool) -> impl Stream<Item = AppResult<Vec<u8>>> {
+    stream::try_unfold(Some(Value::Null), move |offset| async move {
+        let Some(offset) = offset else {
+            return Ok(None);
+        };
+        let mut body = json!({"limit": BATCH, "with_payload": true, "with_vector": with_vectors});
+        if !offset.is_null() {
+            body["offset"] = offset;
+        }
+        let res = qdrant_post(
+            &qdrant_path(&format!("collections/{}/points/scroll", COLLECTION)).await?,
+            body,
+        )
+        .await?;
+        let mut out = vec![];
+        for p in res["result"]["points"].as_array().into_iter().flatten() {
+            let record = Record {
+                id: p["id"].clone(),
+                payload: p["payload"].clone(),
+                vector: with_vectors.then(|| p["vector"].clone()),
+            };
+            out.extend(serde_json::to_vec(&record).unwrap_or_default());
+            out.push(b'\n');
+        }
+        let next = res["result"]["next_page_offset"].clone();
+        Ok(Some((out, (!next.is_null()).then_some(next))))
+    })
+}
+
+#[derive(Deserialize)]
+pub struct ImportOptions {
+    /// Recompute every vector, e.g. after switching embedding models.
+    #[serde(default)]
+    pub reembed: bool,
+    /// Lines already imported by an earlier, interrupted run.
+    #[serde(default)]
+    pub skip: usize,
+}
+
+#[derive(Serialize)]
+pub struct LineError {
+    pub line: usize,
+    pub error: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub failed: Vec<LineError>,
+    /// Pass this back as `skip` to pick up where the import stopped.
+    pub resume_from: usize,
+    /// Set when the import was aborted part way.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Upserts records read from `lines` in batches. Lines that can't be parsed
+/// are reported and skipped; a failed embedding or upsert stops the import so
+/// it can be resumed from `resume_from`. Missing vectors are recomputed from the
+/// `m` payload field, the same text `routes::add` embeds.
+pub async fn import(
+    lines: impl Stream<Item = AppResult<Result<String, String>>>,
+    options: ImportOptions,
+) -> ImportReport {
+    let mut report = ImportReport {
+        resume_from: options.skip,
+        ..Default::default()
+    };
+    let mut lines = Box::pin(lines.enumerate().skip(options.skip));
+    let mut batch: Vec<(usize, Record)> = vec![];
+    let mut seen = options.skip;
+    loop {
+        let next = lines.next().await;
+        if let Some((i, _)) = &next {
+            seen = i + 1;
+        }
+        match &next {
+            Some((_, Ok(Ok(line)))) if line.is_empty() => {}
+            Some((i, Ok(Ok(line)))) => match serde_json::from_str::<Record>(line) {
+                Ok(r) => batch.push((i + 1, r)),
+                Err(e) => report.failed.push(LineError {
+                    line: i + 1,
+                    error: e.to_string(),
+                }),
+            },
+            Some((i, Ok(Err(e)))) => report.failed.push(LineError {
+                line: i + 1,
+                error: e.clone(),
+            }),
+            Some((_, Err(e))) => {
+                report.error = Some(e.to_string());
+                return report;
+            }
+            None => {}
+        }
+        if batch.len() >= BATCH || (next.is_none() && !batch.is_empty()) {
+            let last = batch.last().map(|(l, _)| *l).unwrap_or_default();
+            match flush(std::mem::take(&mut batch), options.reembed, &mut report).await {
+                Ok(()) => report.resume_from = last,
+                Err(e) => {
+                    report.error = Some(e.to_string());
+                    return report;
+                }
+            }
+        }
+        if next.is_none() {
+            report.resume_from = seen;
+            return report;
+        }
+    }
+}
+
+async fn flush(
+    batch: Vec<(usize, Record)>,
+    reembed: bool,
+    report: &mut ImportReport,
+) -> AppResult<()> {
+    let mut ready = vec![];
+    let mut pending = vec![];
+    for (line, r) in batch {
+        if reembed || r.vector.as_ref().is_none_or(Value::is_null) {
+            match r.payload["m"].as_str() {
+                Some(m) => pending.push((line, m.to_string(), r)),
+                None => report.failed.push(LineError {
+                    line,
+                    error: "no vector and no `m` text to embed".to_string(),
+                }),
+            }
+        } else {
+            ready.push(r);
+        }
+    }
+    if !pending.is_empty() {
+        let texts: Vec<String> = pending.iter().map(|(_, m, _)| m.clone()).collect();
+        // an embedding outage aborts like a failed upsert, so a resume retries it
+        let vectors = embeddings(&texts).await?;
+        for ((_, _, mut r), v) in pending.into_iter().zip(vectors) {
+            r.vector = Some(v);
+            ready.push(r);
+        }
+    }
+    if ready.is_empty() {
+        return Ok(());
+    }
+    let n = ready.len();
+    qdrant_put(
+        &qdrant_path(&format!("collections/{}/points?wait=true", COLLECTION)).await?,
+        json!({"points": ready}),
+    )
+    .await?;
+    report.imported += n;
+    Ok(())
+}
@@ -0,0 +1,63 @@
+//! In-process broadcast of things happening in the service. Writers publish
+//! without waiting for anyone; each subscriber gets every event published
+//! after it subscribed, unless it falls more than `CAPACITY` events behind.
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+/// Events kept for slow subscribers before the oldest are dropped for them.
+pub const CAPACITY: usize = 1024;
+
+static BUS: Lazy<broadcast::Sender<Event>> = Lazy::new(|| broadcast::channel(CAPACITY).0);
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A chat message was stored.
+    Message {
+        id: Value,
+        chat: String,
+        visitor: Option<String>,
+        tenant: Option<String>,
+        role: &'static str,
+        text: String,
+        page: Option<String>,
+        date: Value,
+        incomplete: bool,
+    },
+    /// Points were deleted for a chat, address or visitor.
+    Erased {
+        subject: &'static str,
+        chat: Option<String>,
+        deleted: u64,
+    },
+}
+
+impl Event {
+    /// Chat the event belongs to, for subscribers following a single chat.
+    pub fn chat(&self) -> Option<&str> {
+        match self {
+            Event::Message { chat, .. } => Some(chat),
+            Event::Erased { chat, .. } => chat.as_deref(),
+        }
+    }
+
+    /// Tenant the event belongs to, when it is known to belong to one.
+    pub fn tenant(&self) -> Option<&str> {
+        match self {
+            Event::Message { tenant, .. } => tenant.as_deref(),
+            Event::Erased { .. } => None,
+        }
+    }
+}
+
+/// Sends `event` to current subscribers; with none it is simply dropped.
+pub fn publish(event: Event) {
+    let _ = BUS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    BUS.subscribe()
+}
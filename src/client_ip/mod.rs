@@ -0,0 +1,202 @@
+//! The visitor's address behind reverse proxies. A forwarding header is only
+//! believed when the connection comes from a proxy listed in the
+//! `TRUSTED_PROXIES` secret, a comma-separated list of CIDRs such as
+//! `10.0.0.0/8,fd00::/8`, and only the one named by `CLIENT_IP_HEADER`:
+//! `x-forwarded-for` (the default), `forwarded` or `x-real-ip`. The others
+//! are ignored, since the proxy may pass them through from the visitor
+//! untouched. The chain the header describes is walked from the nearest hop
+//! outwards, and the first address that isn't a trusted proxy is the client,
+//! so entries a visitor prepends themselves are never reached.
+
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use once_cell::sync::OnceCell;
+use warp::{http::HeaderMap, Filter};
+
+use crate::constants::SECRETS;
+
+#[cfg(test)]
+mod tests;
+
+static PROXIES: OnceCell<Proxies> = OnceCell::new();
+
+/// The forwarding header the proxies set.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Header {
+    /// RFC 7239 `Forwarded`, its `for=` parameters.
+    Forwarded,
+    XForwardedFor,
+    XRealIp,
+}
+
+impl Header {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "forwarded" => Some(Header::Forwarded),
+            "x-forwarded-for" => Some(Header::XForwardedFor),
+            "x-real-ip" => Some(Header::XRealIp),
+            _ => None,
+        }
+    }
+}
+
+/// Which peers may forward addresses, and in which header.
+#[derive(Clone, Debug, Default)]
+pub struct Proxies {
+    pub trusted: Vec<Cidr>,
+    /// `None` when `CLIENT_IP_HEADER` names no known header, so none is read.
+    pub header: Option<Header>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cidr {
+    net: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Parses `10.0.0.0/8`, `::1/128` or a bare address.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((a, p)) => (a, Some(p.parse::<u8>().ok()?)),
+            None => (s.trim(), None),
+        };
+        let net = addr.parse::<IpAddr>().ok()?.to_canonical();
+        let max = if net.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(Cidr { net, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.net, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Reads `TRUSTED_PROXIES` and `CLIENT_IP_HEADER`; until this runs no proxy
+/// is trusted.
+pub async fn init() {
+    let secrets = SECRETS.lock().await;
+    let spec = secrets.get("TRUSTED_PROXIES").unwrap_or_default();
+    let trusted = spec
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .filter_map(|s| {
+            let c = Cidr::parse(s);
+            if c.is_none() {
+                tracing::warn!(cidr = s, "ignoring unparseable trusted proxy");
+            }
+            c
+        })
+        .collect();
+    let name = secrets
+        .get("CLIENT_IP_HEADER")
+        .unwrap_or_else(|| "x-forwarded-for".to_string());
+    let header = Header::parse(&name);
+    if header.is_none() {
+        tracing::warn!(header = %name, "unknown client ip header, ignoring forwarded addresses");
+    }
+    let _ = PROXIES.set(Proxies { trusted, header });
+}
+
+impl Proxies {
+    fn trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|c| c.contains(ip))
+    }
+}
+
+/// Reads one forwarded address, which may carry a port, brackets or quotes.
+/// `None` for `unknown`, obfuscated identifiers and garbage.
+fn address(s: &str) -> Option<IpAddr> {
+    let s = s.trim().trim_matches('"');
+    s.parse::<IpAddr>()
+        .or_else(|_| s.parse::<SocketAddr>().map(|a| a.ip()))
+        .or_else(|_| s.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
+
+fn values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+}
+
+/// The proxy chain from `header`, client first; entries that can't be read
+/// are `None`.
+fn chain(headers: &HeaderMap, header: Header) -> Vec<Option<IpAddr>> {
+    match header {
+        Header::Forwarded => values(headers, "forwarded")
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(k, _)| k.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, v)| address(v))
+            })
+            .collect(),
+        Header::XForwardedFor => values(headers, "x-forwarded-for").map(address).collect(),
+        Header::XRealIp => headers
+            .get("x-real-ip")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| vec![address(v)])
+            .unwrap_or_default(),
+    }
+}
+
+/// The client address for a connection from `peer` carrying `headers`, given
+/// `proxies`.
+pub fn resolve_with(
+    proxies: &Proxies,
+    peer: Option<SocketAddr>,
+    headers: &HeaderMap,
+) -> Option<IpAddr> {
+    let mut client = peer?.ip().to_canonical();
+    let Some(header) = proxies.header.filter(|_| proxies.trusted(client)) else {
+        return Some(client);
+    };
+    for hop in chain(headers, header).into_iter().rev() {
+        match hop {
+            // an unreadable hop ends what can be vouched for
+            None => break,
+            Some(ip) => {
+                client = ip;
+                if !proxies.trusted(ip) {
+                    break;
+                }
+            }
+        }
+    }
+    Some(client)
+}
+
+/// The client address for a connection from `peer` carrying `headers`, with
+/// the proxies `init` read.
+pub fn resolve(peer: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+    static NONE: Proxies = Proxies {
+        trusted: Vec::new(),
+        header: None,
+    };
+    resolve_with(PROXIES.get().unwrap_or(&NONE), peer, headers)
+}
+
+/// Extracts the client address, to use instead of `warp::addr::remote`.
+pub fn client_ip() -> impl Filter<Extract = (Option<IpAddr>,), Error = Infallible> + Clone {
+    warp::addr::remote()
+        .and(warp::header::headers_cloned())
+        .map(|peer: Option<SocketAddr>, headers: HeaderMap| resolve(peer, &headers))
+}
@@ -0,0 +1,112 @@
+use std::net::{IpAddr, SocketAddr};
+
+use warp::http::{HeaderMap, HeaderValue};
+
+use super::{resolve_with, Cidr, Header, Proxies};
+
+const PROXY: &str = "10.0.0.1:443";
+const CLIENT: &str = "203.0.113.7";
+const FORGED: &str = "198.51.100.66";
+
+fn proxies(header: Header) -> Proxies {
+    Proxies {
+        trusted: vec![Cidr::parse("10.0.0.0/8").unwrap()],
+        header: Some(header),
+    }
+}
+
+fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    let mut h = HeaderMap::new();
+    for (name, value) in pairs {
+        h.append(*name, HeaderValue::from_str(value).unwrap());
+    }
+    h
+}
+
+fn resolve(proxies: &Proxies, peer: &str, pairs: &[(&'static str, &str)]) -> IpAddr {
+    resolve_with(proxies, peer.parse::<SocketAddr>().ok(), &headers(pairs)).unwrap()
+}
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn untrusted_peer_is_the_client_whatever_it_forwards() {
+    let p = proxies(Header::XForwardedFor);
+    let peer = "192.0.2.10:5000";
+    let h = [
+        ("x-forwarded-for", CLIENT),
+        ("forwarded", "for=203.0.113.7"),
+        ("x-real-ip", CLIENT),
+    ];
+    assert_eq!(resolve(&p, peer, &h), ip("192.0.2.10"));
+    assert_eq!(resolve(&Proxies::default(), PROXY, &h), ip("10.0.0.1"));
+}
+
+#[test]
+fn forged_leftmost_forwarded_for_is_never_reached() {
+    let p = proxies(Header::XForwardedFor);
+    let forged = format!("{}, {}, 10.1.2.3", FORGED, CLIENT);
+    assert_eq!(
+        resolve(&p, PROXY, &[("x-forwarded-for", &forged)]),
+        ip(CLIENT)
+    );
+    // the same chain split over several header lines
+    let h = [("x-forwarded-for", FORGED), ("x-forwarded-for", CLIENT)];
+    assert_eq!(resolve(&p, PROXY, &h), ip(CLIENT));
+}
+
+#[test]
+fn only_the_configured_header_is_read() {
+    let forged = format!("for={}", FORGED);
+    let h = [("forwarded", forged.as_str()), ("x-forwarded-for", CLIENT)];
+    assert_eq!(
+        resolve(&proxies(Header::XForwardedFor), PROXY, &h),
+        ip(CLIENT)
+    );
+    assert_eq!(resolve(&proxies(Header::Forwarded), PROXY, &h), ip(FORGED));
+    assert_eq!(
+        resolve(&proxies(Header::XRealIp), PROXY, &h),
+        ip("10.0.0.1")
+    );
+    let unknown = Proxies {
+        header: Header::parse("x-client-ip"),
+        ..proxies(Header::XForwardedFor)
+    };
+    assert_eq!(resolve(&unknown, PROXY, &h), ip("10.0.0.1"));
+}
+
+#[test]
+fn forwarded_elements_carry_ports_brackets_and_quotes() {
+    let p = proxies(Header::Forwarded);
+    let h = [(
+        "forwarded",
+        r#"for="[2001:db8::1]:4711";proto=https, for=10.0.0.2"#,
+    )];
+    assert_eq!(resolve(&p, PROXY, &h), ip("2001:db8::1"));
+}
+
+#[test]
+fn unparseable_hop_ends_the_chain() {
+    let p = proxies(Header::XForwardedFor);
+    // garbage before the client is never reached
+    let h = [("x-forwarded-for", "not-an-ip, 203.0.113.7")];
+    assert_eq!(resolve(&p, PROXY, &h), ip(CLIENT));
+    // garbage nearer than the client can't be vouched for, so the last
+    // trusted hop is kept
+    let h = [("x-forwarded-for", "203.0.113.7, unknown, 10.1.2.3")];
+    assert_eq!(resolve(&p, PROXY, &h), ip("10.1.2.3"));
+    let h = [("forwarded", "for=_hidden")];
+    assert_eq!(
+        resolve(&proxies(Header::Forwarded), PROXY, &h),
+        ip("10.0.0.1")
+    );
+}
+
+#[test]
+fn mapped_ipv4_peers_match_ipv4_ranges() {
+    let p = proxies(Header::XForwardedFor);
+    let h = [("x-forwarded-for", CLIENT)];
+    assert_eq!(resolve(&p, "[::ffff:10.0.0.1]:443", &h), ip(CLIENT));
+}
@@ -0,0 +1,304 @@
+//! Server-rendered pages of the admin console under `/admin`.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+use crate::{
+    app::AppResult,
+    constants::{COLLECTION, SITE_CHAT_MESSAGE_CATEGORY},
+    qdrant::{integer_index, qdrant_path, qdrant_post},
+    store::MESSAGE_CATEGORY,
+    transcript::{date_range, escape_html as esc, timestamp, Message},
+};
+
+/// Chats listed on the front page.
+const CONVERSATIONS: usize = 50;
+/// Most recent messages scanned to find them, for each unit `d` comes in.
+/// Chats with no message among them, or only undated ones, are left out; the
+/// message counts shown only include scanned messages.
+const SCAN: usize = 2048;
+const PREVIEW_CHARS: usize = 120;
+
+pub struct Conversation {
+    pub id: String,
+    pub messages: usize,
+    pub last: Option<DateTime<Utc>>,
+    pub page: Option<String>,
+    /// Visitor's country, from the `g` of their messages.
+    pub country: Option<String>,
+    /// Start of the chat's first question.
+    pub preview: String,
+    first: Option<DateTime<Utc>>,
+}
+
+/// Recently active chats, newest first, optionally only those from visitors
+/// in `country`.
+pub async fn conversations(country: Option<&str>) -> AppResult<Vec<Conversation>> {
+    integer_index(COLLECTION, "d").await?;
+    let mut chats: HashMap<String, Conversation> = HashMap::new();
+    // `d` in milliseconds and `d` in seconds, each scanned newest first
+    for dated in date_range(None, None)["should"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        let res = qdrant_post(
+            &qdrant_path(&format!("collections/{}/points/scroll", COLLECTION)).await?,
+            json!({
+                "limit": SCAN,
+                "with_payload": ["i", "u", "m", "d", "p", "g"],
+                "order_by": {"key": "d", "direction": "desc"},
+                "filter": {"must": [
+                    {"key": "c", "match": {"any": [MESSAGE_CATEGORY, SITE_CHAT_MESSAGE_CATEGORY]}},
+                    dated,
+                ]},
+            }),
+        )
+        .await?;
+        for p in res["result"]["points"].as_array().into_iter().flatten() {
+            let payload = &p["payload"];
+            let Some(id) = payload["i"].as_str() else {
+                continue;
+            };
+            let time = timestamp(&payload["d"]);
+            let c = chats.entry(id.to_string()).or_insert_with(|| Conversation {
+                id: id.to_string(),
+                messages: 0,
+                last: None,
+                page: None,
+                country: None,
+                preview: String::new(),
+                first: None,
+            });
+            c.messages += 1;
+            c.last = c.last.max(time);
+            if let Some(g) = payload["g"]["country"].as_str() {
+                c.country = Some(g.to_string());
+            }
+            if payload["u"].as_i64() == Some(1)
+                && (c.preview.is_empty() || time.is_some() && time < c.first)
+            {
+                c.first = time;
+                c.preview = payload["m"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .chars()
+                    .take(PREVIEW_CHARS)
+                    .collect();
+                c.page = payload["p"].as_str().map(str::to_string);
+            }
+        }
+    }
+    let mut chats: Vec<Conversation> = chats
+        .into_values()
+        .filter(|c| country.is_none_or(|country| c.country.as_deref() == Some(country)))
+        .collect();
+    chats.sort_by(|a, b| b.last.cmp(&a.last).then_with(|| a.id.cmp(&b.id)));
+    chats.truncate(CONVERSATIONS);
+    Ok(chats)
+}
+
+/// Percent-encodes `s` as one URL path segment, leaving only unreserved
+/// characters as they are.
+pub fn encode_segment(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Reverses `encode_segment`, as warp hands path parameters over still
+/// encoded. A segment that doesn't decode to UTF-8 is kept as it is.
+pub fn decode_segment(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match hex {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).unwrap_or_else(|_| s.to_string())
+}
+
+/// Wraps `body` in the console's chrome.
+pub fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>{0} · admin</title>\
+         <style>body{{font-family:sans-serif;max-width:64em;margin:auto;padding:0 1em}}\
+         nav a{{margin-right:1em}}table{{border-collapse:collapse;width:100%}}\
+         td,th{{text-align:left;padding:.3em;border-bottom:1px solid #ddd;vertical-align:top}}\
+         .user{{background:#eef}}.assistant{{background:#efe}}\
+         .msg{{padding:.5em;margin:.5em 0;border-radius:4px}}\
+         small{{color:#666}}.error{{color:#a00}}form.inline{{display:inline}}</style>\
+         </head><body><nav><a href=\"/admin\">Conversations</a>\
+         <a href=\"/admin/search\">Search</a><a href=\"/admin/visitors\">Visitors</a>\
+         <form class=\"inline\" method=\"post\" action=\"/admin/logout\">\
+         <button>Sign out</button></form></nav><h1>{0}</h1>\n{1}</body></html>\n",
+        esc(title),
+        body
+    )
+}
+
+pub fn login(error: Option<&str>) -> String {
+    format!(
+        "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>Sign in · admin</title>\
+         </head><body style=\"font-family:sans-serif;max-width:24em;margin:4em auto\">\
+         <h1>Sign in</h1>{}<form method=\"post\" action=\"/admin/login\">\
+         <input type=\"password\" name=\"key\" placeholder=\"Admin key\" autofocus> \
+         <button>Sign in</button></form></body></html>\n",
+        error
+            .map(|e| format!("<p class=\"error\">{}</p>", esc(e)))
+            .unwrap_or_default()
+    )
+}
+
+fn time(t: &Option<DateTime<Utc>>) -> String {
+    t.map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+pub fn conversations_page(chats: &[Conversation], country: Option<&str>) -> String {
+    let mut body = format!(
+        "<form method=\"get\" action=\"/admin\">\
+         <input name=\"country\" value=\"{}\" size=\"4\" placeholder=\"Country\"> \
+         <button>Filter</button></form>\n\
+         <table><tr><th>Chat</th><th>Last message</th><th>Messages</th>\
+         <th>Country</th><th>Page</th><th>First question</th></tr>\n",
+        esc(country.unwrap_or_default())
+    );
+    for c in chats {
+        body.push_str(&format!(
+            "<tr><td><a href=\"/admin/chat/{6}\">{0}</a></td><td>{1}</td><td>{2}</td>\
+             <td>{3}</td><td>{4}</td><td>{5}</td></tr>\n",
+            esc(&c.id),
+            time(&c.last),
+            c.messages,
+            esc(c.country.as_deref().unwrap_or_default()),
+            esc(c.page.as_deref().unwrap_or_default()),
+            esc(&c.preview),
+            encode_segment(&c.id),
+        ));
+    }
+    body.push_str("</table>\n");
+    page("Conversations", &body)
+}
+
+pub fn chat_page(id: &str, messages: &[Message]) -> String {
+    let mut body = format!(
+        "<p>Export: <a href=\"/admin/chat/{0}/export?format=md\">Markdown</a> · \
+         <a href=\"/admin/chat/{0}/export?format=jsonl\">JSONL</a> · \
+         <a href=\"/admin/chat/{0}/export?format=csv\">CSV</a> · \
+         <a href=\"/admin/chat/{0}/export?format=html\">HTML</a></p>\
+         <form method=\"post\" action=\"/admin/chat/{0}/delete\" \
+         onsubmit=\"return confirm('Delete this chat for good?')\">\
+         <button>Delete chat</button></form>\n",
+        encode_segment(id)
+    );
+    if messages.is_empty() {
+        body.push_str("<p>No messages.</p>\n");
+    }
+    for m in messages {
+        body.push_str(&format!(
+            "<div class=\"msg {}\"><small>{} · {}{}</small><p>{}</p></div>\n",
+            m.role,
+            m.role,
+            time(&m.time),
+            m.page
+                .as_ref()
+                .map(|p| format!(" · {}", esc(p)))
+                .unwrap_or_default(),
+            esc(&m.text).replace('\n', "<br>"),
+        ));
+    }
+    page(&format!("Chat {}", id), &body)
+}
+
+/// Parses the search form's filter box: one `key=value` per line, values read
+/// as JSON when they parse and as strings otherwise.
+pub fn parse_filter(s: &str) -> Option<HashMap<String, Value>> {
+    let f: HashMap<String, Value> = s
+        .lines()
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| {
+            let v = v.trim();
+            (
+                k.trim().to_string(),
+                serde_json::from_str(v).unwrap_or_else(|_| json!(v)),
+            )
+        })
+        .filter(|(k, _)| !k.is_empty())
+        .collect();
+    (!f.is_empty()).then_some(f)
+}
+
+pub fn search_page(q: &str, filter: &str, results: Option<&Value>) -> String {
+    let mut body = format!(
+        "<form method=\"get\" action=\"/admin/search\">\
+         <p><input name=\"q\" value=\"{}\" size=\"60\" placeholder=\"Question\"></p>\
+         <p><textarea name=\"f\" rows=\"3\" cols=\"40\" placeholder=\"key=value per line\">{}</textarea></p>\
+         <button>Search</button></form>\n",
+        esc(q),
+        esc(filter)
+    );
+    if let Some(results) = results {
+        body.push_str("<table><tr><th>Score</th><th>ID</th><th>Role</th><th>Text</th></tr>\n");
+        for r in results.as_array().into_iter().flatten() {
+            body.push_str(&format!(
+                "<tr><td>{:.3}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                r["score"].as_f64().unwrap_or_default(),
+                esc(&r["id"].to_string()),
+                if r["payload"]["u"].as_i64() == Some(1) {
+                    "user"
+                } else {
+                    "assistant"
+                },
+                esc(r["payload"]["m"].as_str().unwrap_or_default()),
+            ));
+        }
+        body.push_str("</table>\n");
+    }
+    page("Search", &body)
+}
+
+pub fn visitors_page(since: Option<i64>, groups: Option<&Value>) -> String {
+    let mut body = format!(
+        "<form method=\"get\" action=\"/admin/visitors\">\
+         <input name=\"d\" value=\"{}\" placeholder=\"Since (epoch)\"> \
+         <button>Show</button></form>\n",
+        since.map(|d| d.to_string()).unwrap_or_default()
+    );
+    if let Some(groups) = groups {
+        body.push_str("<table><tr><th>IP</th><th>First message</th></tr>\n");
+        for g in groups["groups"].as_array().into_iter().flatten() {
+            let ip = match &g["id"] {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            };
+            body.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td></tr>\n",
+                esc(&ip),
+                esc(&g["hits"][0]["payload"].to_string()),
+            ));
+        }
+        body.push_str("</table>\n");
+    }
+    page("Visitors", &body)
+}
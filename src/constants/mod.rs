@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use shuttle_runtime::SecretStore;
 use tokio::sync::Mutex;
 use once_cell::sync::Lazy;
@@ -5,9 +7,21 @@ use once_cell::sync::Lazy;
 use crate::app::AppError;
 
 pub type AppResult<T> = Result<T, AppError>;
-pub const COLLECTION: &'static str = "i";
-pub const I_ID: &'static str = "b4ea369a-d21e-40b4-afe7-4e84a4a7cd91";
-pub const SITE_CHAT_MESSAGE_CATEGORY: &'static str = "scm";
+pub const COLLECTION: &str = "i";
+pub const I_ID: &str = "b4ea369a-d21e-40b4-afe7-4e84a4a7cd91";
+/// Vectorless collection holding audit records, e.g. of erasures.
+pub const AUDIT_COLLECTION: &str = "audit";
+/// Vectorless collection recording merged visitor IDs.
+pub const VISITOR_COLLECTION: &str = "visitors";
+pub const SITE_CHAT_MESSAGE_CATEGORY: &str = "scm";
 pub static SECRETS: Lazy<Mutex<SecretStore>> =
     Lazy::new(|| Mutex::new(SecretStore::new(std::collections::BTreeMap::new())));
+/// Maximum number of embeddings kept in memory before the cache is reset.
+pub const EMBEDDING_CACHE_SIZE: usize = 1024;
+pub const QDRANT_READ_TIMEOUT: Duration = Duration::from_secs(10);
+pub const QDRANT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+pub const QDRANT_RETRIES: u32 = 3;
+pub const EMBEDDING_TIMEOUT: Duration = Duration::from_secs(30);
+pub const EMBEDDING_RETRIES: u32 = 2;
+pub const LLM_TIMEOUT: Duration = Duration::from_secs(60);
 pub const PRIVATE: &[&str] = &[""];
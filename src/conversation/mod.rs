@@ -0,0 +1,245 @@
+//! Conversation-level vectors: each chat gets one extra point in the `cv`
+//! category whose vector is the mean of its message vectors, so whole chats
+//! can be compared. `store::save` folds new messages in as they are written;
+//! a chat without one, e.g. from before this existed, gets it rebuilt from
+//! its messages on first use.
+//!
+//! Concurrent writes to one chat can each miss the other's messages in the
+//! mean; a rebuild, or simply later messages, smooth that out.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    app::AppResult,
+    constants::{COLLECTION, SITE_CHAT_MESSAGE_CATEGORY},
+    qdrant::{qdrant_path, qdrant_post, qdrant_put},
+    search::search,
+    store::{self, MESSAGE_CATEGORY},
+    tenant::{Tenant, TENANT_FIELD},
+    transcript::timestamp,
+};
+
+pub const CONVERSATION_CATEGORY: &str = "cv";
+const PAGE: usize = 256;
+pub const MAX_SIMILAR: usize = 20;
+
+/// Chat IDs are per tenant, so two tenants' chats of one ID get a vector
+/// each.
+fn point_id(chat: &str, tenant: &Option<String>) -> String {
+    let name = match tenant {
+        Some(tenant) => format!("qdrant-warp:conversation:{}:{}", tenant, chat),
+        None => format!("qdrant-warp:conversation:{}", chat),
+    };
+    uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_URL, name.as_bytes()).to_string()
+}
+
+fn floats(v: &Value) -> Option<Vec<f64>> {
+    v.as_array()?.iter().map(Value::as_f64).collect()
+}
+
+/// Running sum of message vectors and how many went in.
+#[derive(Default)]
+struct Mean {
+    sum: Vec<f64>,
+    n: u64,
+}
+
+/// A chat and its tenant.
+type Key = (String, Option<String>);
+
+impl Mean {
+    fn add(&mut self, v: &[f64], weight: u64) {
+        if self.sum.is_empty() {
+            self.sum = vec![0.0; v.len()];
+        }
+        if self.sum.len() != v.len() {
+            return;
+        }
+        for (s, x) in self.sum.iter_mut().zip(v) {
+            *s += x * weight as f64;
+        }
+        self.n += weight;
+    }
+
+    fn vector(&self) -> Vec<f64> {
+        self.sum.iter().map(|s| s / self.n.max(1) as f64).collect()
+    }
+}
+
+async fn upsert(means: &HashMap<Key, Mean>) -> AppResult<()> {
+    let points: Vec<Value> = means
+        .iter()
+        .filter(|(_, m)| m.n > 0)
+        .map(|((chat, tenant), m)| {
+            let mut payload =
+                json!({"c": CONVERSATION_CATEGORY, "i": chat, "n": m.n, "d": store::now()});
+            if let Some(o) = tenant {
+                payload[TENANT_FIELD] = json!(o);
+            }
+            json!({"id": point_id(chat, tenant), "vector": m.vector(), "payload": payload})
+        })
+        .collect();
+    if points.is_empty() {
+        return Ok(());
+    }
+    qdrant_put(
+        &qdrant_path(&format!("collections/{}/points?wait=true", COLLECTION)).await?,
+        json!({ "points": points }),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Folds freshly stored `(chat, tenant, vector)` triples into their chats'
+/// vectors.
+pub async fn absorb(added: &[(String, Option<String>, Value)]) -> AppResult<()> {
+    let mut means: HashMap<Key, Mean> = HashMap::new();
+    let ids: Vec<String> = {
+        let mut chats: Vec<(&String, &Option<String>)> =
+            added.iter().map(|(c, t, _)| (c, t)).collect();
+        chats.sort();
+        chats.dedup();
+        chats.into_iter().map(|(c, t)| point_id(c, t)).collect()
+    };
+    let existing = qdrant_post(
+        &qdrant_path(&format!("collections/{}/points", COLLECTION)).await?,
+        json!({"ids": ids, "with_payload": ["i", "n", TENANT_FIELD], "with_vector": true}),
+    )
+    .await?;
+    for p in existing["result"].as_array().into_iter().flatten() {
+        let (Some(chat), Some(v)) = (p["payload"]["i"].as_str(), floats(&p["vector"])) else {
+            continue;
+        };
+        let tenant = p["payload"][TENANT_FIELD].as_str().map(str::to_string);
+        let mut mean = Mean::default();
+        mean.add(&v, p["payload"]["n"].as_u64().unwrap_or(1));
+        means.insert((chat.to_string(), tenant), mean);
+    }
+    for (chat, tenant, v) in added {
+        if let Some(v) = floats(v) {
+            means
+                .entry((chat.clone(), tenant.clone()))
+                .or_default()
+                .add(&v, 1);
+        }
+    }
+    upsert(&means).await
+}
+
+/// Recomputes the vector of `chat` from all its messages within tenant `t`.
+pub async fn rebuild(chat: &str, t: &Tenant) -> AppResult<Option<Vec<f64>>> {
+    let mut mean = Mean::default();
+    let mut offset = Value::Null;
+    loop {
+        let mut body = json!({
+            "limit": PAGE,
+            "with_payload": false,
+            "with_vector": true,
+            "filter": t.scope(Some(json!({"must": [
+                {"key": "i", "match": {"value": chat}},
+                {"key": "c", "match": {"any": [MESSAGE_CATEGORY, SITE_CHAT_MESSAGE_CATEGORY]}},
+            ]}))),
+        });
+        if !offset.is_null() {
+            body["offset"] = offset;
+        }
+        let res = qdrant_post(
+            &qdrant_path(&format!("collections/{}/points/scroll", COLLECTION)).await?,
+            body,
+        )
+        .await?;
+        for p in res["result"]["points"].as_array().into_iter().flatten() {
+            if let Some(v) = floats(&p["vector"]) {
+                mean.add(&v, 1);
+            }
+        }
+        offset = res["result"]["next_page_offset"].clone();
+        if offset.is_null() {
+            break;
+        }
+    }
+    if mean.n == 0 {
+        return Ok(None);
+    }
+    let v = mean.vector();
+    upsert(&HashMap::from([((chat.to_string(), t.0.clone()), mean)])).await?;
+    Ok(Some(v))
+}
+
+/// The stored vector of `chat`, rebuilt if it has none yet; `None` when the
+/// chat has no messages within tenant `t`.
+pub async fn vector(chat: &str, t: &Tenant) -> AppResult<Option<Vec<f64>>> {
+    let res = qdrant_post(
+        &qdrant_path(&format!("collections/{}/points", COLLECTION)).await?,
+        json!({"ids": [point_id(chat, &t.0)], "with_payload": [TENANT_FIELD], "with_vector": true}),
+    )
+    .await?;
+    let p = &res["result"][0];
+    match floats(&p["vector"]) {
+        Some(v) => Ok(t.owns(&p["payload"]).then_some(v)),
+        None => rebuild(chat, t).await,
+    }
+}
+
+#[derive(Serialize)]
+pub struct Similar {
+    pub chat: String,
+    pub score: f64,
+    pub messages: u64,
+    pub updated: Option<DateTime<Utc>>,
+}
+
+/// Up to `limit` other chats of tenant `t` closest to `chat`, best first.
+/// `None` when `chat` has no messages within `t`.
+pub async fn similar(chat: &str, limit: usize, t: &Tenant) -> AppResult<Option<Vec<Similar>>> {
+    let Some(v) = vector(chat, t).await? else {
+        return Ok(None);
+    };
+    let hits = search(
+        json!(v),
+        t.scope(Some(json!({
+            "must": [{"key": "c", "match": {"value": CONVERSATION_CATEGORY}}],
+            "must_not": [{"key": "i", "match": {"value": chat}}],
+        }))),
+        limit.clamp(1, MAX_SIMILAR),
+        json!(["i", "n", "d"]),
+    )
+    .await?;
+    Ok(Some(
+        hits.iter()
+            .filter_map(|h| {
+                Some(Similar {
+                    chat: h["payload"]["i"].as_str()?.to_string(),
+                    score: h["score"].as_f64().unwrap_or_default(),
+                    messages: h["payload"]["n"].as_u64().unwrap_or_default(),
+                    updated: timestamp(&h["payload"]["d"]),
+                })
+            })
+            .collect(),
+    ))
+}
+
+/// Drops the vectors of `chats`, in every tenant, after some of their
+/// messages were deleted; they are rebuilt from what remains when next needed.
+pub async fn forget(chats: &[String]) -> AppResult<()> {
+    if chats.is_empty() {
+        return Ok(());
+    }
+    qdrant_post(
+        &qdrant_path(&format!(
+            "collections/{}/points/delete?wait=true",
+            COLLECTION
+        ))
+        .await?,
+        json!({"filter": {"must": [
+            {"key": "c", "match": {"value": CONVERSATION_CATEGORY}},
+            {"key": "i", "match": {"any": chats}},
+        ]}}),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,206 @@
+//! Deleting everything stored about a chat, a client address or a visitor,
+//! e.g. to honor a data deletion request. Each erasure leaves an audit record
+//! in the vectorless `audit` collection; the record identifies the subject
+//! only by a fingerprint so the log doesn't keep what was erased.
+
+use std::collections::HashSet;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    analytics,
+    app::AppResult,
+    bus::{self, Event},
+    constants::{AUDIT_COLLECTION, COLLECTION},
+    conversation, faq, privacy,
+    qdrant::{qdrant_path, qdrant_post, qdrant_put, vectorless_collection},
+    store,
+    telemetry::request_id,
+    util, visitor,
+};
+
+const PAGE: usize = 256;
+
+#[derive(Clone, Debug)]
+pub enum Subject {
+    /// Chat ID, payload key `i`.
+    Chat(String),
+    /// Client address, payload key `a` (`ip` on older points).
+    Address(String),
+    /// Visitor ID, payload key `v`, along with the IDs merged with it.
+    Visitor(String),
+}
+
+impl Subject {
+    pub fn parse(kind: &str, value: String) -> Option<Self> {
+        match kind {
+            "chat" => Some(Subject::Chat(value)),
+            "ip" => Some(Subject::Address(value)),
+            "visitor" => Some(Subject::Visitor(value)),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Subject::Chat(_) => "chat",
+            Subject::Address(_) => "ip",
+            Subject::Visitor(_) => "visitor",
+        }
+    }
+
+    fn value(&self) -> &str {
+        match self {
+            Subject::Chat(v) | Subject::Address(v) | Subject::Visitor(v) => v,
+        }
+    }
+
+    /// Every point belonging to the subject, whatever its category. An
+    /// address matches both verbatim and in the form `privacy` stores it; a
+    /// visitor only under the ID given, see `visitor::family` for the rest.
+    pub fn filter(&self, privacy: &privacy::Config) -> Value {
+        match self {
+            Subject::Chat(id) => json!({"must": [{"key": "i", "match": {"value": id}}]}),
+            Subject::Address(a) => {
+                let stored = privacy.pseudonymize(a);
+                json!({"should": [
+                    {"key": "a", "match": {"any": [a, stored]}},
+                    {"key": "ip", "match": {"any": [a, stored]}},
+                ]})
+            }
+            Subject::Visitor(v) => json!({"must": [{"key": "v", "match": {"value": v}}]}),
+        }
+    }
+
+    /// Whether erasing the subject can't hit anyone else. With `IP_TRUNCATE`
+    /// on, an address is stored as its whole /24 or /64, shared with other
+    /// visitors.
+    pub fn erasable(&self, privacy: &privacy::Config) -> bool {
+        !matches!(self, Subject::Address(_)) || !privacy.truncate
+    }
+
+    /// Stable stand-in for the subject in audit records.
+    fn fingerprint(&self) -> String {
+        uuid::Uuid::new_v5(
+            &uuid::Uuid::NAMESPACE_URL,
+            format!("qdrant-warp:erasure:{}:{}", self.kind(), self.value()).as_bytes(),
+        )
+        .to_string()
+    }
+}
+
+#[derive(Serialize)]
+pub struct Erasure {
+    /// ID of the audit record.
+    pub audit: String,
+    pub subject: &'static str,
+    pub deleted: u64,
+}
+
+/// Chats with points matching `filter`.
+pub async fn chats(filter: &Value) -> AppResult<Vec<String>> {
+    let mut chats = HashSet::new();
+    let mut offset = Value::Null;
+    loop {
+        let mut body = json!({"limit": PAGE, "with_payload": ["i"], "filter": filter});
+        if !offset.is_null() {
+            body["offset"] = offset;
+        }
+        let res = qdrant_post(
+            &qdrant_path(&format!("collections/{}/points/scroll", COLLECTION)).await?,
+            body,
+        )
+        .await?;
+        chats.extend(
+            res["result"]["points"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|p| p["payload"]["i"].as_str().map(str::to_string)),
+        );
+        offset = res["result"]["next_page_offset"].clone();
+        if offset.is_null() {
+            return Ok(chats.into_iter().collect());
+        }
+    }
+}
+
+/// Deletes every point of `subject` and records the erasure. The audit record
+/// is written even when nothing matched, as proof the request was handled.
+pub async fn erase(subject: Subject, reason: Option<String>) -> AppResult<Erasure> {
+    // a visitor's messages may still carry the IDs merged with it
+    let (filter, visitors) = match &subject {
+        Subject::Visitor(v) => {
+            let ids = visitor::family(v).await?;
+            (json!({"must": [{"key": "v", "match": {"any": ids}}]}), ids)
+        }
+        _ => (subject.filter(&privacy::config().await), vec![]),
+    };
+    let touched = match subject {
+        Subject::Chat(_) => vec![],
+        _ => chats(&filter).await?,
+    };
+    let deleted = qdrant_post(
+        &qdrant_path(&format!("collections/{}/points/count", COLLECTION)).await?,
+        json!({"filter": filter, "exact": true}),
+    )
+    .await?["result"]["count"]
+        .as_u64()
+        .unwrap_or(0);
+    qdrant_post(
+        &qdrant_path(&format!(
+            "collections/{}/points/delete?wait=true",
+            COLLECTION
+        ))
+        .await?,
+        json!({ "filter": filter }),
+    )
+    .await?;
+    // a chat's own vector goes with its `i`; others only lost some messages
+    conversation::forget(&touched).await?;
+    analytics::forget();
+    faq::forget().await;
+    util::forget_embeddings().await;
+    if !visitors.is_empty() {
+        visitor::forget(&visitors).await?;
+    }
+
+    vectorless_collection(AUDIT_COLLECTION).await?;
+    let audit = uuid::Uuid::now_v7().to_string();
+    qdrant_put(
+        &qdrant_path(&format!(
+            "collections/{}/points?wait=true",
+            AUDIT_COLLECTION
+        ))
+        .await?,
+        json!({"points": [{
+            "id": audit,
+            "vector": {},
+            "payload": {
+                "action": "erase",
+                "subject": subject.kind(),
+                "fingerprint": subject.fingerprint(),
+                "deleted": deleted,
+                "reason": reason,
+                "request_id": request_id(),
+                "d": store::now(),
+            },
+        }]}),
+    )
+    .await?;
+    tracing::info!(subject = subject.kind(), deleted, audit = %audit, "erased");
+    bus::publish(Event::Erased {
+        subject: subject.kind(),
+        chat: match &subject {
+            Subject::Chat(id) => Some(id.clone()),
+            _ => None,
+        },
+        deleted,
+    });
+    Ok(Erasure {
+        audit,
+        subject: subject.kind(),
+        deleted,
+    })
+}
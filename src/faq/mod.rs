@@ -0,0 +1,317 @@
+//! FAQ mining: visitor questions from a date range are clustered by their
+//! vectors with k-means (cosine, k-means++ seeding), clusters ranked by size
+//! become the FAQ, and each question is labelled with its cluster in the `q`
+//! payload field (`q.run`, `q.cluster`) so `/search` can filter on it.
+//!
+//! A background run over the last `FAQ_WINDOW_DAYS` (default 30) happens every
+//! `FAQ_INTERVAL_HOURS` when that secret is set; `/admin/faq` runs one on
+//! demand and shows the latest.
+
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use once_cell::sync::Lazy;
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+use crate::{
+    app::{AppError, AppResult},
+    constants::{COLLECTION, SECRETS, SITE_CHAT_MESSAGE_CATEGORY},
+    qdrant::{qdrant_path, qdrant_post},
+    store::MESSAGE_CATEGORY,
+    transcript::date_range,
+};
+
+const PAGE: usize = 256;
+const ITERATIONS: usize = 25;
+const MAX_K: usize = 50;
+/// Most questions one run clusters, whatever `max_points` asks for.
+const MAX_POINTS: usize = 10_000;
+const DEFAULT_WINDOW_DAYS: i64 = 30;
+
+static LATEST: Lazy<Mutex<Option<Report>>> = Lazy::new(Default::default);
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Options {
+    #[serde(default)]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+    /// Number of clusters; about √(n/2) when left out.
+    #[serde(default)]
+    pub k: Option<usize>,
+    /// Members listed per cluster besides the representative.
+    #[serde(default = "default_samples")]
+    pub samples: usize,
+    /// Questions clustered at most, to bound the work; capped at
+    /// `MAX_POINTS`.
+    #[serde(default = "default_max_points")]
+    pub max_points: usize,
+    /// Write cluster labels back to the questions.
+    #[serde(default = "default_label")]
+    pub label: bool,
+}
+
+fn default_samples() -> usize {
+    5
+}
+
+fn default_max_points() -> usize {
+    2000
+}
+
+fn default_label() -> bool {
+    true
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Question {
+    pub id: Value,
+    pub text: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Cluster {
+    /// 1 for the most asked.
+    pub rank: usize,
+    pub size: usize,
+    /// Member closest to the cluster's centre.
+    pub representative: Question,
+    pub samples: Vec<Question>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Report {
+    /// Written to `q.run` of the labelled questions.
+    pub run: String,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub questions: usize,
+    pub clusters: Vec<Cluster>,
+}
+
+async fn questions(o: &Options) -> AppResult<Vec<(Question, Vec<f32>)>> {
+    let max = o.max_points.min(MAX_POINTS);
+    let mut out = vec![];
+    let mut offset = Value::Null;
+    while out.len() < max {
+        let mut body = json!({
+            "limit": PAGE.min(max - out.len()),
+            "with_payload": ["m"],
+            "with_vector": true,
+            "filter": {"must": [
+                {"key": "u", "match": {"value": 1}},
+                {"key": "c", "match": {"any": [MESSAGE_CATEGORY, SITE_CHAT_MESSAGE_CATEGORY]}},
+                date_range(o.from, o.to),
+            ]},
+        });
+        if !offset.is_null() {
+            body["offset"] = offset;
+        }
+        let res = qdrant_post(
+            &qdrant_path(&format!("collections/{}/points/scroll", COLLECTION)).await?,
+            body,
+        )
+        .await?;
+        for p in res["result"]["points"].as_array().into_iter().flatten() {
+            let Some(v) = p["vector"].as_array() else {
+                continue;
+            };
+            let v: Vec<f32> = v
+                .iter()
+                .filter_map(Value::as_f64)
+                .map(|x| x as f32)
+                .collect();
+            out.push((
+                Question {
+                    id: p["id"].clone(),
+                    text: p["payload"]["m"].as_str().unwrap_or_default().to_string(),
+                },
+                normalized(v),
+            ));
+        }
+        offset = res["result"]["next_page_offset"].clone();
+        if offset.is_null() {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+fn normalized(mut v: Vec<f32>) -> Vec<f32> {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+    v
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Index of the centroid closest to `v`, and the cosine similarity to it.
+fn nearest(v: &[f32], centroids: &[Vec<f32>]) -> (usize, f32) {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (i, dot(v, c)))
+        .fold((0, f32::MIN), |best, x| if x.1 > best.1 { x } else { best })
+}
+
+/// Assigns each of the unit `vectors` to one of `k` clusters.
+fn kmeans(vectors: &[Vec<f32>], k: usize) -> Vec<usize> {
+    let mut rng = rand::thread_rng();
+    let mut centroids = vec![vectors[rng.gen_range(0..vectors.len())].clone()];
+    while centroids.len() < k {
+        // k-means++: far-away points are likelier next seeds
+        let weights: Vec<f32> = vectors
+            .iter()
+            .map(|v| (1.0 - nearest(v, &centroids).1).max(0.0).powi(2))
+            .collect();
+        match WeightedIndex::new(&weights) {
+            Ok(w) => centroids.push(vectors[w.sample(&mut rng)].clone()),
+            Err(_) => break,
+        }
+    }
+    let mut assignment = vec![usize::MAX; vectors.len()];
+    for _ in 0..ITERATIONS {
+        let mut changed = false;
+        for (v, a) in vectors.iter().zip(assignment.iter_mut()) {
+            let (c, _) = nearest(v, &centroids);
+            changed |= *a != c;
+            *a = c;
+        }
+        if !changed {
+            break;
+        }
+        let dims = vectors[0].len();
+        let mut sums = vec![vec![0.0; dims]; centroids.len()];
+        for (v, &a) in vectors.iter().zip(&assignment) {
+            sums[a].iter_mut().zip(v).for_each(|(s, x)| *s += x);
+        }
+        for (c, s) in centroids.iter_mut().zip(sums) {
+            // an emptied cluster keeps its old centre
+            if s.iter().any(|x| *x != 0.0) {
+                *c = normalized(s);
+            }
+        }
+    }
+    assignment
+}
+
+/// Clusters the questions `o` selects and, unless told not to, labels them.
+pub async fn run(o: Options) -> AppResult<Report> {
+    let questions = questions(&o).await?;
+    let run = uuid::Uuid::now_v7().to_string();
+    let mut report = Report {
+        run: run.clone(),
+        from: o.from,
+        to: o.to,
+        questions: questions.len(),
+        clusters: vec![],
+    };
+    if questions.len() < 2 {
+        return Ok(report);
+    }
+    let k =
+        o.k.unwrap_or_else(|| ((questions.len() as f64 / 2.0).sqrt() as usize).max(2))
+            .clamp(1, MAX_K.min(questions.len()));
+    let (questions, vectors): (Vec<Question>, Vec<Vec<f32>>) = questions.into_iter().unzip();
+    let (assignment, vectors) = tokio::task::spawn_blocking(move || (kmeans(&vectors, k), vectors))
+        .await
+        .map_err(|e| AppError::new("clustering questions", e))?;
+
+    let mut members: Vec<Vec<usize>> = vec![vec![]; k];
+    for (i, &a) in assignment.iter().enumerate() {
+        members[a].push(i);
+    }
+    members.retain(|m| !m.is_empty());
+    members.sort_by_key(|m| std::cmp::Reverse(m.len()));
+    for (rank, m) in members.iter().enumerate() {
+        let dims = vectors[m[0]].len();
+        let mut centre = vec![0.0; dims];
+        for &i in m {
+            centre
+                .iter_mut()
+                .zip(&vectors[i])
+                .for_each(|(s, x)| *s += x);
+        }
+        let centre = normalized(centre);
+        let mut by_closeness = m.clone();
+        by_closeness
+            .sort_by(|&a, &b| dot(&vectors[b], &centre).total_cmp(&dot(&vectors[a], &centre)));
+        report.clusters.push(Cluster {
+            rank: rank + 1,
+            size: m.len(),
+            representative: questions[by_closeness[0]].clone(),
+            samples: by_closeness
+                .iter()
+                .skip(1)
+                .take(o.samples)
+                .map(|&i| questions[i].clone())
+                .collect(),
+        });
+        if o.label {
+            let ids: Vec<&Value> = m.iter().map(|&i| &questions[i].id).collect();
+            qdrant_post(
+                &qdrant_path(&format!(
+                    "collections/{}/points/payload?wait=true",
+                    COLLECTION
+                ))
+                .await?,
+                json!({"payload": {"q": {"run": run, "cluster": rank + 1}}, "points": ids}),
+            )
+            .await?;
+        }
+    }
+    tracing::info!(run = %run, questions = report.questions, clusters = report.clusters.len(), "faq clustered");
+    *LATEST.lock().await = Some(report.clone());
+    Ok(report)
+}
+
+/// The report of the last run in this process.
+pub async fn latest() -> Option<Report> {
+    LATEST.lock().await.clone()
+}
+
+/// Drops the last report, after points were deleted: it quotes questions.
+pub async fn forget() {
+    *LATEST.lock().await = None;
+}
+
+/// Starts the periodic run when `FAQ_INTERVAL_HOURS` is set.
+pub async fn spawn() {
+    let secrets = SECRETS.lock().await;
+    let Some(hours) = secrets
+        .get("FAQ_INTERVAL_HOURS")
+        .and_then(|h| h.parse::<u64>().ok())
+        .filter(|h| *h > 0)
+    else {
+        return;
+    };
+    let window = secrets
+        .get("FAQ_WINDOW_DAYS")
+        .and_then(|d| d.parse().ok())
+        .unwrap_or(DEFAULT_WINDOW_DAYS);
+    drop(secrets);
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_secs(hours * 60 * 60));
+        loop {
+            tick.tick().await;
+            let o = Options {
+                from: Some(Utc::now() - TimeDelta::days(window)),
+                to: None,
+                k: None,
+                samples: default_samples(),
+                max_points: default_max_points(),
+                label: true,
+            };
+            if let Err(e) = run(o).await {
+                tracing::error!("{:#?}", e);
+            }
+        }
+    });
+}
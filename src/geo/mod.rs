@@ -0,0 +1,60 @@
+//! Offline GeoIP lookups against a MaxMind database (GeoLite2 City or
+//! Country, or any compatible MMDB file) at the path in the `GEOIP_DB` secret.
+//! Without one, nothing is looked up.
+
+use std::net::IpAddr;
+
+use maxminddb::{geoip2, Reader};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+
+use crate::constants::SECRETS;
+
+static READER: OnceCell<Reader<Vec<u8>>> = OnceCell::new();
+
+/// Where an address is, stored on user messages as `g`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Location {
+    /// ISO 3166-1 country code, filterable as `g.country`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// ISO 3166-2 code of the largest subdivision, without the country part.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// English city name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+}
+
+/// Loads the database named by `GEOIP_DB` into memory.
+pub async fn init() {
+    let Some(path) = SECRETS.lock().await.get("GEOIP_DB") else {
+        return;
+    };
+    match Reader::open_readfile(&path) {
+        Ok(reader) => {
+            tracing::info!(path, kind = %reader.metadata.database_type, "geoip database loaded");
+            let _ = READER.set(reader);
+        }
+        Err(e) => tracing::error!(path, "loading geoip database: {}", e),
+    }
+}
+
+/// Looks `ip` up, `None` when there is no database or no entry.
+pub fn locate(ip: IpAddr) -> Option<Location> {
+    let city: geoip2::City = READER.get()?.lookup(ip).ok()?;
+    let location = Location {
+        country: city.country.and_then(|c| c.iso_code).map(str::to_string),
+        region: city
+            .subdivisions
+            .and_then(|s| s.into_iter().next())
+            .and_then(|s| s.iso_code)
+            .map(str::to_string),
+        city: city
+            .city
+            .and_then(|c| c.names)
+            .and_then(|n| n.get("en").copied())
+            .map(str::to_string),
+    };
+    (location != Location::default()).then_some(location)
+}
@@ -0,0 +1,278 @@
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    app::AppResult,
+    store::{external_point_id, save, Message},
+    tenant::Tenant,
+};
+
+/// Messages embedded and upserted per batch. Conversations are never split
+/// across batches, so a batch may run over this.
+pub const BATCH: usize = 64;
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+/// A single message line.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessageRecord {
+    pub external_id: String,
+    pub chat: String,
+    pub role: Role,
+    pub text: String,
+    #[serde(default)]
+    pub date: Value,
+    #[serde(default)]
+    pub page: Option<String>,
+    #[serde(default)]
+    pub ip: Option<String>,
+}
+
+/// A message inside a conversation line; chat and page come from the
+/// conversation, and the external ID defaults to `{conversation}:{index}`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TurnRecord {
+    #[serde(default)]
+    pub external_id: Option<String>,
+    pub role: Role,
+    pub text: String,
+    #[serde(default)]
+    pub date: Value,
+    #[serde(default)]
+    pub ip: Option<String>,
+}
+
+/// A whole conversation on one line.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConversationRecord {
+    pub external_id: String,
+    pub chat: String,
+    #[serde(default)]
+    pub page: Option<String>,
+    pub messages: Vec<TurnRecord>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Error,
+}
+
+#[derive(Serialize)]
+pub struct RecordReport {
+    pub line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub status: Status,
+    /// Points written for this record.
+    pub points: usize,
+    /// External IDs of the record's messages that couldn't be written.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+pub struct Report {
+    pub ok: usize,
+    pub failed: usize,
+    pub records: Vec<RecordReport>,
+}
+
+impl Report {
+    fn push(&mut self, r: RecordReport) {
+        match r.status {
+            Status::Ok => self.ok += 1,
+            Status::Error => self.failed += 1,
+        }
+        self.records.push(r);
+    }
+}
+
+fn required(field: &str, v: &str) -> Result<(), String> {
+    if v.trim().is_empty() {
+        Err(format!("`{}` must not be empty", field))
+    } else {
+        Ok(())
+    }
+}
+
+/// Parses and validates one line into the messages it stands for, as
+/// `tenant`'s.
+fn parse(line: &str, tenant: &Tenant) -> Result<(String, Vec<Message>), String> {
+    let v: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    if v.get("messages").is_some() {
+        let c: ConversationRecord = serde_json::from_value(v).map_err(|e| e.to_string())?;
+        required("external_id", &c.external_id)?;
+        required("chat", &c.chat)?;
+        if c.messages.is_empty() {
+            return Err("`messages` must not be empty".to_string());
+        }
+        let messages = c
+            .messages
+            .into_iter()
+            .enumerate()
+            .map(|(n, t)| {
+                required("text", &t.text)?;
+                let external_id = t
+                    .external_id
+                    .unwrap_or_else(|| format!("{}:{}", c.external_id, n));
+                required("external_id", &external_id)?;
+                Ok(Message {
+                    id: external_point_id(&external_id, tenant),
+                    chat: c.chat.clone(),
+                    user: t.role == Role::User,
+                    text: t.text,
+                    page: c.page.clone(),
+                    date: t.date,
+                    ip: t.ip,
+                    external_id: Some(external_id),
+                    visitor: None,
+                    tenant: tenant.0.clone(),
+                    incomplete: false,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok((c.external_id, messages))
+    } else {
+        let m: MessageRecord = serde_json::from_value(v).map_err(|e| e.to_string())?;
+        required("external_id", &m.external_id)?;
+        required("chat", &m.chat)?;
+        required("text", &m.text)?;
+        Ok((
+            m.external_id.clone(),
+            vec![Message {
+                id: external_point_id(&m.external_id, tenant),
+                chat: m.chat,
+                user: m.role == Role::User,
+                text: m.text,
+                page: m.page,
+                date: m.date,
+                ip: m.ip,
+                external_id: Some(m.external_id),
+                visitor: None,
+                tenant: tenant.0.clone(),
+                incomplete: false,
+            }],
+        ))
+    }
+}
+
+/// A parsed record waiting for its batch to be written.
+struct Pending {
+    line: usize,
+    external_id: String,
+    messages: Vec<Message>,
+}
+
+async fn flush(batch: Vec<Pending>, report: &mut Report) {
+    let messages: Vec<Message> = batch
+        .iter()
+        .flat_map(|p| p.messages.iter().cloned())
+        .collect();
+    if let Err(e) = save(&messages).await {
+        tracing::error!("{:#?}", e);
+        for p in batch {
+            report.push(save_alone(p).await);
+        }
+        return;
+    }
+    for p in batch {
+        report.push(RecordReport {
+            line: p.line,
+            external_id: Some(p.external_id),
+            status: Status::Ok,
+            points: p.messages.len(),
+            failed: vec![],
+            error: None,
+        });
+    }
+}
+
+/// Writes a record's messages one at a time after its batch failed, so the
+/// report names the messages that can't be written rather than the batch.
+async fn save_alone(p: Pending) -> RecordReport {
+    let mut points = 0;
+    let mut failed = vec![];
+    let mut error = None;
+    for m in &p.messages {
+        match save(std::slice::from_ref(m)).await {
+            Ok(()) => points += 1,
+            Err(e) => {
+                failed.push(m.external_id.clone().unwrap_or_default());
+                error = Some(e.to_string());
+            }
+        }
+    }
+    RecordReport {
+        line: p.line,
+        external_id: Some(p.external_id),
+        status: if failed.is_empty() {
+            Status::Ok
+        } else {
+            Status::Error
+        },
+        points,
+        failed,
+        error,
+    }
+}
+
+/// Ingests NDJSON `lines` of messages or conversations. Point IDs derive from
+/// each message's external ID, so re-running an ingestion updates the points
+/// it wrote before instead of adding new ones. When a batch fails, its
+/// messages are retried one by one, so each record reports what was written.
+/// Everything is stamped as tenant `t`'s.
+pub async fn ingest(
+    lines: impl Stream<Item = AppResult<Result<String, String>>>,
+    t: &Tenant,
+) -> Report {
+    let mut report = Report::default();
+    let mut lines = Box::pin(lines.enumerate());
+    let mut batch: Vec<Pending> = vec![];
+    let mut size = 0;
+    while let Some((i, line)) = lines.next().await {
+        let line_no = i + 1;
+        let line = match line {
+            Ok(Ok(l)) if l.is_empty() => continue,
+            Ok(l) => l,
+            Err(e) => Err(e.to_string()),
+        };
+        match line.and_then(|l| parse(&l, t)) {
+            Ok((external_id, messages)) => {
+                size += messages.len();
+                batch.push(Pending {
+                    line: line_no,
+                    external_id,
+                    messages,
+                });
+            }
+            Err(error) => report.push(RecordReport {
+                line: line_no,
+                external_id: None,
+                status: Status::Error,
+                points: 0,
+                failed: vec![],
+                error: Some(error),
+            }),
+        }
+        if size >= BATCH {
+            flush(std::mem::take(&mut batch), &mut report).await;
+            size = 0;
+        }
+    }
+    flush(batch, &mut report).await;
+    report.records.sort_by_key(|r| r.line);
+    report
+}
@@ -0,0 +1,392 @@
+//! Knowledge-base documents. A document is stripped to plain text, split into
+//! overlapping chunks along its headings, and each chunk stored as a point in
+//! the `kb` category so `/search` finds it next to chat messages.
+//!
+//! Chunk payload keys: `c` = "kb", `m` chunk text, `k` document ID, `n` chunk
+//! index, `t` document title, `s` source URL, `h` section heading and `o` the
+//! tenant. Document IDs are per tenant.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    app::{AppError, AppResult},
+    constants::COLLECTION,
+    qdrant::{qdrant_path, qdrant_post, qdrant_put},
+    tenant::{Tenant, TENANT_FIELD},
+    util::embeddings,
+};
+
+pub const KB_CATEGORY: &str = "kb";
+/// Target chunk length in characters.
+pub const CHUNK_CHARS: usize = 1000;
+/// Characters repeated from the end of one chunk at the start of the next.
+pub const CHUNK_OVERLAP: usize = 200;
+/// Chunks embedded and upserted per request, so a long document doesn't
+/// become one oversized call to either service.
+const BATCH: usize = 64;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    Markdown,
+    Html,
+}
+
+#[derive(Deserialize)]
+pub struct Document {
+    pub title: String,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub format: Format,
+    pub content: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Chunk {
+    pub heading: Option<String>,
+    pub text: String,
+}
+
+fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let end = match rest.find(';') {
+            Some(e) if e <= 10 => e,
+            _ => {
+                out.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|d| d.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+const BLOCK_TAGS: &[&str] = &[
+    "p",
+    "div",
+    "br",
+    "li",
+    "tr",
+    "section",
+    "article",
+    "ul",
+    "ol",
+    "table",
+    "blockquote",
+    "pre",
+    "header",
+    "footer",
+    "main",
+    "nav",
+    "hr",
+];
+
+/// Reduces HTML to Markdown-ish text: headings become `#` lines, block
+/// elements line breaks, scripts and styles vanish, other tags are dropped.
+pub fn strip_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    let mut skip_until: Option<String> = None;
+    while let Some(i) = rest.find('<') {
+        if skip_until.is_none() {
+            out.push_str(&decode_entities(&rest[..i]));
+        }
+        rest = &rest[i..];
+        let end = match rest.find('>') {
+            Some(e) => e,
+            None => break,
+        };
+        let tag = rest[1..end].trim();
+        rest = &rest[end + 1..];
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if let Some(until) = &skip_until {
+            if closing && &name == until {
+                skip_until = None;
+            }
+            continue;
+        }
+        match name.as_str() {
+            "script" | "style" | "head" if !closing && !tag.ends_with('/') => {
+                skip_until = Some(name)
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                if closing {
+                    out.push('\n');
+                } else {
+                    let level = name[1..].parse().unwrap_or(1);
+                    out.push('\n');
+                    out.push_str(&"#".repeat(level));
+                    out.push(' ');
+                }
+            }
+            n if BLOCK_TAGS.contains(&n) => out.push('\n'),
+            _ => {}
+        }
+    }
+    if skip_until.is_none() {
+        out.push_str(&decode_entities(rest));
+    }
+    out
+}
+
+/// Removes inline Markdown: images and links keep their text, emphasis and
+/// code markers go.
+fn strip_inline(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(i) = rest.find('[') {
+        let (before, after) = rest.split_at(i);
+        out.push_str(before.strip_suffix('!').unwrap_or(before));
+        match (after.find("]("), after.find(')')) {
+            (Some(close), Some(paren)) if close < paren => {
+                out.push_str(&after[1..close]);
+                rest = &after[paren + 1..];
+            }
+            _ => {
+                out.push('[');
+                rest = &after[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out.replace("**", "").replace("__", "").replace('`', "")
+}
+
+/// Splits Markdown into `(heading, body)` sections, dropping markup.
+pub fn sections(markdown: &str) -> Vec<(Option<String>, String)> {
+    let mut sections = vec![(None, String::new())];
+    for line in markdown.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            continue;
+        }
+        let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            sections.push((Some(strip_inline(trimmed[hashes..].trim())), String::new()));
+            continue;
+        }
+        let text = trimmed.trim_start_matches('>').trim_start();
+        let text = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|m| text.strip_prefix(m))
+            .unwrap_or(text);
+        let body = &mut sections.last_mut().unwrap().1;
+        body.push_str(&strip_inline(text));
+        body.push('\n');
+    }
+    sections
+        .into_iter()
+        .filter(|(h, b)| h.is_some() || !b.trim().is_empty())
+        .collect()
+}
+
+/// Cuts a word longer than `CHUNK_CHARS` into pieces of that many
+/// characters, so it can't make a chunk on its own.
+fn pieces(word: &str) -> Vec<&str> {
+    let mut out = vec![];
+    let mut rest = word;
+    while !rest.is_empty() {
+        let end = rest
+            .char_indices()
+            .nth(CHUNK_CHARS)
+            .map_or(rest.len(), |(i, _)| i);
+        out.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    out
+}
+
+/// Splits `text` into pieces of at most `CHUNK_CHARS` characters, each
+/// starting about `CHUNK_OVERLAP` characters before the previous one ended, on
+/// word boundaries. Lengths count characters, not bytes.
+fn split(text: &str) -> Vec<String> {
+    let words: Vec<&str> = text.split_whitespace().flat_map(pieces).collect();
+    let lens: Vec<usize> = words.iter().map(|w| w.chars().count()).collect();
+    let mut chunks = vec![];
+    let mut start = 0;
+    while start < words.len() {
+        let mut end = start;
+        let mut len = 0;
+        while end < words.len() && (end == start || len + lens[end] < CHUNK_CHARS) {
+            len += lens[end] + 1;
+            end += 1;
+        }
+        chunks.push(words[start..end].join(" "));
+        if end == words.len() {
+            break;
+        }
+        let mut back = end;
+        let mut overlap = 0;
+        while back > start + 1 && overlap + lens[back - 1] < CHUNK_OVERLAP {
+            back -= 1;
+            overlap += lens[back] + 1;
+        }
+        start = back;
+    }
+    chunks
+}
+
+/// Strips `content` according to `format` and cuts it into chunks.
+pub fn chunk(content: &str, format: Format) -> Vec<Chunk> {
+    let sections = match format {
+        Format::Text => vec![(None, content.to_string())],
+        Format::Markdown => sections(content),
+        Format::Html => sections(&strip_html(content)),
+    };
+    sections
+        .into_iter()
+        .flat_map(|(heading, body)| {
+            let pieces = split(&body);
+            let pieces = if pieces.is_empty() {
+                heading.iter().cloned().collect()
+            } else {
+                pieces
+            };
+            pieces.into_iter().map(move |text| Chunk {
+                heading: heading.clone(),
+                text,
+            })
+        })
+        .collect()
+}
+
+fn chunk_id(document: &str, n: usize, t: &Tenant) -> String {
+    let name = match &t.0 {
+        Some(tenant) => format!("qdrant-warp:kb:{}:{}:{}", tenant, document, n),
+        None => format!("qdrant-warp:kb:{}:{}", document, n),
+    };
+    uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_URL, name.as_bytes()).to_string()
+}
+
+fn document_filter(id: &str, t: &Tenant) -> Value {
+    t.scope(Some(json!({"must": [
+        {"key": "c", "match": {"value": KB_CATEGORY}},
+        {"key": "k", "match": {"value": id}},
+    ]})))
+    .unwrap_or_default()
+}
+
+/// Stores `doc` as tenant `t`'s document `id`, replacing any earlier version.
+/// New chunks overwrite old ones in place and leftovers past the new end are
+/// deleted afterwards, so the document is never missing from search.
+pub async fn put(id: &str, doc: Document, t: &Tenant) -> AppResult<usize> {
+    let chunks = chunk(&doc.content, doc.format);
+    if chunks.is_empty() {
+        return Err(AppError::new_plain("document has no text"));
+    }
+    let texts: Vec<String> = chunks
+        .iter()
+        .map(|c| match &c.heading {
+            Some(h) if h != &c.text => format!("{}\n{}", h, c.text),
+            _ => c.text.clone(),
+        })
+        .collect();
+    for (b, batch) in texts.chunks(BATCH).enumerate() {
+        let vectors = embeddings(batch).await?;
+        let points: Vec<Value> = batch
+            .iter()
+            .zip(vectors)
+            .enumerate()
+            .map(|(i, (text, vector))| {
+                let n = b * BATCH + i;
+                let mut payload = json!({
+                    "c": KB_CATEGORY,
+                    "m": text,
+                    "k": id,
+                    "n": n,
+                    "t": doc.title,
+                    "s": doc.url,
+                    "h": chunks[n].heading,
+                });
+                if let Some(o) = &t.0 {
+                    payload[TENANT_FIELD] = json!(o);
+                }
+                json!({"id": chunk_id(id, n, t), "payload": payload, "vector": vector})
+            })
+            .collect();
+        qdrant_put(
+            &qdrant_path(&format!("collections/{}/points?wait=true", COLLECTION)).await?,
+            json!({ "points": points }),
+        )
+        .await?;
+    }
+    let stale = json!({"must": [
+        document_filter(id, t),
+        {"key": "n", "range": {"gte": chunks.len()}},
+    ]});
+    qdrant_post(
+        &qdrant_path(&format!(
+            "collections/{}/points/delete?wait=true",
+            COLLECTION
+        ))
+        .await?,
+        json!({ "filter": stale }),
+    )
+    .await?;
+    Ok(chunks.len())
+}
+
+/// Deletes every chunk of tenant `t`'s document `id`, returning how many
+/// there were.
+pub async fn delete(id: &str, t: &Tenant) -> AppResult<u64> {
+    let count = qdrant_post(
+        &qdrant_path(&format!("collections/{}/points/count", COLLECTION)).await?,
+        json!({"filter": document_filter(id, t), "exact": true}),
+    )
+    .await?["result"]["count"]
+        .as_u64()
+        .unwrap_or(0);
+    qdrant_post(
+        &qdrant_path(&format!(
+            "collections/{}/points/delete?wait=true",
+            COLLECTION
+        ))
+        .await?,
+        json!({ "filter": document_filter(id, t) }),
+    )
+    .await?;
+    Ok(count)
+}
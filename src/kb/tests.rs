@@ -0,0 +1,114 @@
+use super::{
+    chunk, decode_entities, sections, split, strip_html, Chunk, Format, CHUNK_CHARS, CHUNK_OVERLAP,
+};
+
+#[test]
+fn named_and_numeric_entities_decode() {
+    assert_eq!(
+        decode_entities("a &amp; b &lt;c&gt; &quot;d&quot; &#39;e&#x27; &#X41;"),
+        "a & b <c> \"d\" 'e' A"
+    );
+    assert_eq!(
+        decode_entities("caf&eacute; &#xD800; & x;"),
+        "caf&eacute; &#xD800; & x;"
+    );
+    assert_eq!(decode_entities("&nbsp;é&amp;"), " é&");
+}
+
+#[test]
+fn html_keeps_text_and_headings() {
+    let html = "<html><head><title>Ignored</title></head><body>\
+                <h2 class=\"x\">Prices &amp; plans</h2><p>One<br/>Two</p>\
+                <script>alert('<p>')</script><style>p{}</style><b>bold</b></body></html>";
+    let text = strip_html(html);
+    assert!(text.contains("\n## Prices & plans\n"));
+    assert!(text.contains("\nOne\nTwo\n"));
+    assert!(text.contains("bold"));
+    assert!(!text.contains("alert"));
+    assert!(!text.contains("p{}"));
+    assert!(!text.contains("Ignored"));
+}
+
+#[test]
+fn unclosed_tag_is_kept_as_text() {
+    assert_eq!(strip_html("1 < 2 &amp; more"), "1 < 2 & more");
+}
+
+#[test]
+fn markdown_sections_follow_headings() {
+    let md = "Intro *text*\n\n# First\n- item [link](https://x.example)\n```\n## Second **bold**\n> quoted `code`\n#not a heading";
+    assert_eq!(
+        sections(md),
+        vec![
+            (None, "Intro *text*\n\n".to_string()),
+            (Some("First".to_string()), "item link\n".to_string()),
+            (
+                Some("Second bold".to_string()),
+                "quoted code\n#not a heading\n".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn empty_sections_keep_their_heading() {
+    assert_eq!(
+        chunk("# Empty\n\n# Full\nbody", Format::Markdown),
+        vec![
+            Chunk {
+                heading: Some("Empty".to_string()),
+                text: "Empty".to_string()
+            },
+            Chunk {
+                heading: Some("Full".to_string()),
+                text: "body".to_string()
+            },
+        ]
+    );
+}
+
+#[test]
+fn chunks_overlap_on_word_boundaries() {
+    let text = (0..600)
+        .map(|i| format!("w{:03}", i))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let chunks = split(&text);
+    assert!(chunks.len() > 1);
+    for c in &chunks {
+        assert!(c.chars().count() <= CHUNK_CHARS);
+        assert!(c.split(' ').all(|w| w.len() == 4));
+    }
+    for pair in chunks.windows(2) {
+        let tail: Vec<&str> = pair[0]
+            .split(' ')
+            .rev()
+            .take_while(|w| pair[1].contains(*w))
+            .collect();
+        let overlap = tail.iter().map(|w| w.len() + 1).sum::<usize>();
+        assert!(overlap > 0 && overlap <= CHUNK_OVERLAP + 5, "{}", overlap);
+    }
+    assert!(chunks.last().unwrap().ends_with("w599"));
+}
+
+#[test]
+fn lengths_count_characters() {
+    // 500 two-byte words of one character fit in one chunk of 999 characters
+    let text = vec!["é"; 500].join(" ");
+    assert_eq!(split(&text), vec![text.clone()]);
+}
+
+#[test]
+fn long_words_are_cut_between_characters() {
+    let word = "日".repeat(CHUNK_CHARS * 2 + 10);
+    let chunks = split(&word);
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks[0].chars().count(), CHUNK_CHARS);
+    assert_eq!(chunks[2].chars().count(), 10);
+    assert_eq!(chunks.concat(), word);
+}
+
+#[test]
+fn blank_text_has_no_chunks() {
+    assert!(split(" \n\t ").is_empty());
+}
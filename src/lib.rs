@@ -3,4 +3,29 @@ pub mod routes;
 pub mod qdrant;
 pub mod util;
 pub mod app;
+pub mod metrics;
+pub mod telemetry;
+pub mod resilience;
+pub mod auth;
+pub mod backup;
+pub mod transcript;
+pub mod store;
+pub mod ingest;
+pub mod kb;
 
+pub mod search;
+pub mod llm;
+pub mod rag;
+pub mod bus;
+pub mod console;
+pub mod erasure;
+pub mod retention;
+pub mod privacy;
+pub mod client_ip;
+pub mod geo;
+pub mod visitor;
+pub mod conversation;
+pub mod faq;
+pub mod analytics;
+pub mod tenant;
+pub mod ratelimit;
@@ -0,0 +1,135 @@
+//! Client for an OpenAI-compatible chat completions endpoint, configured with
+//! `LLM_URL` (the full `/chat/completions` URL), `LLM_MODEL` and an optional
+//! `LLM_KEY`.
+
+use futures_util::{stream, Stream, StreamExt};
+use serde_json::{json, Value};
+
+use crate::{
+    app::{AppError, AppResult},
+    constants::{LLM_TIMEOUT, SECRETS},
+    resilience::{call, Failure, Policy, LLM_BREAKER},
+};
+
+/// Completions are slow and not free, so a failed one is not retried.
+const LLM_POLICY: Policy = Policy {
+    timeout: LLM_TIMEOUT,
+    retries: 0,
+    idempotent: false,
+};
+
+pub struct Config {
+    pub url: String,
+    pub model: String,
+    pub key: Option<String>,
+}
+
+pub async fn config() -> AppResult<Config> {
+    let secrets = SECRETS.lock().await;
+    Ok(Config {
+        url: secrets
+            .get("LLM_URL")
+            .ok_or(AppError::new_plain("LLM_URL not found in env"))?,
+        model: secrets
+            .get("LLM_MODEL")
+            .unwrap_or_else(|| "gpt-4o-mini".to_string()),
+        key: secrets.get("LLM_KEY"),
+    })
+}
+
+/// Posts `messages` to the completions endpoint and returns the response once
+/// its status is known; `stream` asks for server-sent chunks.
+async fn send(messages: &[Value], stream: bool) -> AppResult<reqwest::Response> {
+    let c = config().await?;
+    let mut req = reqwest::Client::new()
+        .post(&c.url)
+        .json(&json!({"model": c.model, "messages": messages, "stream": stream}));
+    if let Some(key) = c.key {
+        req = req.bearer_auth(key);
+    }
+    call(&LLM_BREAKER, LLM_POLICY, || async {
+        let res = req
+            .try_clone()
+            .ok_or(Failure::permanent(AppError::new_plain(
+                "llm request body cannot be replayed",
+            )))?
+            .send()
+            .await
+            .map_err(|e| Failure::from_reqwest("sending chat completion request", e))?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(Failure::from_status("llm", status, &body));
+        }
+        Ok(res)
+    })
+    .await
+}
+
+/// Asks the model to continue `messages` and returns its answer.
+pub async fn complete(messages: &[Value]) -> AppResult<String> {
+    let res = send(messages, false)
+        .await?
+        .json::<Value>()
+        .await
+        .map_err(|e| AppError::new("parsing chat completion response", e))?;
+    res["choices"][0]["message"]["content"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or(AppError::new_plain("chat completion has no content"))
+}
+
+/// Like `complete`, but yields the answer piece by piece as the model writes
+/// it. The stream ends at the endpoint's `[DONE]` marker; the endpoint hanging
+/// up before sending it ends the stream with an error, as the answer may be cut
+/// short.
+pub async fn stream(
+    messages: &[Value],
+) -> AppResult<impl Stream<Item = AppResult<String>> + Send + 'static> {
+    let body = send(messages, true).await?.bytes_stream();
+    Ok(stream::unfold(
+        (Box::pin(body), Vec::<u8>::new(), false),
+        |(mut body, mut buf, done)| async move {
+            if done {
+                return None;
+            }
+            loop {
+                if let Some(i) = buf.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = buf.drain(..=i).collect();
+                    let line = String::from_utf8_lossy(&line);
+                    let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+                        continue;
+                    };
+                    if data == "[DONE]" {
+                        return None;
+                    }
+                    let delta = match serde_json::from_str::<Value>(data) {
+                        Ok(v) => v["choices"][0]["delta"]["content"]
+                            .as_str()
+                            .unwrap_or_default()
+                            .to_string(),
+                        Err(e) => {
+                            let e = AppError::new("parsing chat completion chunk", e);
+                            return Some((Err(e), (body, buf, true)));
+                        }
+                    };
+                    if delta.is_empty() {
+                        continue;
+                    }
+                    return Some((Ok(delta), (body, buf, false)));
+                }
+                match body.next().await {
+                    Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                    Some(Err(e)) => {
+                        let e = AppError::new("reading chat completion stream", e);
+                        return Some((Err(e), (body, buf, true)));
+                    }
+                    None => {
+                        let e = AppError::new_plain("chat completion stream ended without [DONE]");
+                        return Some((Err(e), (body, buf, true)));
+                    }
+                }
+            }
+        },
+    ))
+}
@@ -1,16 +1,45 @@
 use anyhow::Result;
 use qdrant_warp::constants::{COLLECTION, SECRETS};
+use qdrant_warp::auth::{admin, operator, recover, signed_in};
+use qdrant_warp::client_ip::{self, client_ip};
+use qdrant_warp::rag::MAX_QUESTION_BYTES;
+use qdrant_warp::ratelimit::{limited, REPLY_LIMITER};
 use qdrant_warp::routes::add::{add, Add};
+use qdrant_warp::routes::analytics::analytics;
+use qdrant_warp::routes::by_ip::{handle_by_ip, ByIP};
+use qdrant_warp::routes::backup::{export, import};
 use qdrant_warp::routes::chat::chat;
+use qdrant_warp::routes::chat_export::chat_export;
 use qdrant_warp::routes::chat_from::chat_from;
 use qdrant_warp::routes::chats::chats;
 use qdrant_warp::routes::chats_from::chats_from;
+use qdrant_warp::routes::console;
+use qdrant_warp::routes::erase::erase;
+use qdrant_warp::routes::faq::{latest_faq, run_faq};
+use qdrant_warp::routes::feed::feed;
+use qdrant_warp::routes::health::{healthz, readyz};
+use qdrant_warp::routes::ingest::ingest;
+use qdrant_warp::routes::kb::{delete_document, put_document};
 use qdrant_warp::routes::next_id::next_id;
+use qdrant_warp::routes::pseudonym::pseudonym;
+use qdrant_warp::routes::reply::reply;
+use qdrant_warp::routes::reply_stream::reply_stream;
+use qdrant_warp::routes::retention::retention;
+use qdrant_warp::routes::visitor::{merge_visitors, visitor_summary};
+use qdrant_warp::routes::similar::similar_chats;
+use qdrant_warp::routes::search::{
+    handle_facets, handle_group_search, handle_recommend, handle_search, Facets, GroupSearch,
+    Recommend, SearchQuery,
+};
 use qdrant_warp::util::embedding;
 use qdrant_warp::{
     app::{AppError, AppResult},
     constants::PRIVATE,
-    qdrant::{qdrant_path, qdrant_post, qdrant_put},
+    metrics::{metrics, observe_http},
+    qdrant::{qdrant_get, qdrant_path, qdrant_post, qdrant_put},
+    telemetry::{self, request_span, with_request_id, REQUEST_ID_HEADER},
+    tenant::{tenant, Tenant, TENANT_FIELD, TENANT_HEADER},
+    visitor::{visitor, VISITOR_HEADER},
     util::random_embedding,
 };
 use serde::{Deserialize, Serialize};
@@ -24,15 +53,51 @@ async fn warp(
 ) -> shuttle_warp::ShuttleWarp<(impl Reply,)> {
     let mut secrets_ = SECRETS.lock().await;
     *secrets_ = secrets;
-
-    let cors = warp::cors()
-        .allow_any_origin()
+    drop(secrets_);
+    telemetry::init().await;
+    client_ip::init().await;
+    qdrant_warp::geo::init().await;
+    qdrant_warp::tenant::init().await;
+    qdrant_warp::retention::spawn().await;
+    qdrant_warp::faq::spawn().await;
+
+    // the visitor cookie only travels to sites listed in `CORS_ORIGINS`,
+    // e.g. `https://a.example,https://b.example`; others may still call in
+    let origins: Vec<String> = SECRETS
+        .lock()
+        .await
+        .get("CORS_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|o| o.trim().trim_end_matches('/').to_string())
+        .filter(|o| {
+            o.parse::<warp::http::Uri>().is_ok_and(|u| {
+                u.scheme().is_some() && u.authority().is_some() && u.path() == "/"
+            })
+        })
+        .collect();
+    let cors = if origins.is_empty() {
+        warp::cors().allow_any_origin()
+    } else {
+        warp::cors()
+            .allow_origins(origins.iter().map(String::as_str))
+            .allow_credentials(true)
+    };
+    let cors = cors
         .allow_methods(vec!["GET", "POST", "PUT", "DELETE"])
-        .allow_headers(vec!["Content-Type"]);
+        .allow_headers(vec![
+            "Content-Type",
+            "Authorization",
+            REQUEST_ID_HEADER,
+            VISITOR_HEADER,
+            TENANT_HEADER,
+        ])
+        .expose_headers(vec![REQUEST_ID_HEADER, VISITOR_HEADER]);
 
     let get_route = warp::path::end()
         .and(warp::get())
         .and(warp::query::<ItemQuery>())
+        .and(tenant())
         .and_then(handle_get);
 
     // let delete_route = warp::path::end()
@@ -48,20 +113,23 @@ async fn warp(
     let add = warp::path::end()
         .and(warp::post())
         .and(warp::body::json::<Add>())
-        .and(warp::filters::addr::remote())
+        .and(client_ip())
+        .and(visitor())
+        .and(tenant())
         .then(add);
 
     let search_route = warp::path("search")
         .and(warp::path::end())
         .and(warp::post())
         .and(warp::body::json::<SearchQuery>())
+        .and(tenant())
         .and_then(handle_search);
 
-    let routes = get_route
+    let public_routes = get_route
         // .or(delete_route)
         // .or(set_route)
         .or(add)
-        .or(search_route)
+        .or(search_route.clone())
         .or(search_route)
         .or(warp::path("i")
             .and(warp::path::end())
@@ -71,22 +139,198 @@ async fn warp(
             .and(warp::path::end())
             .and(warp::post())
             .and(warp::body::json::<GroupSearch>())
+            .and(tenant())
             .and_then(handle_group_search))
-        .or(warp::path!("chats").and(warp::get()).then(chats))
-        .or(warp::path!("chats" / i64)
-            .and(warp::get())
-            .then(chats_from))
-        .or(warp::path!("chat" / String).and(warp::get()).then(chat))
-        .or(warp::path!("chat_from" / String / i64)
+        .or(warp::path("facets")
+            .and(warp::path::end())
             .and(warp::get())
-            .then(chat_from))
+            .and(warp::query::<Facets>())
+            .and(tenant())
+            .and_then(handle_facets))
+        .or(warp::path("recommend")
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::body::json::<Recommend>())
+            .and(tenant())
+            .and_then(handle_recommend))
         .or(warp::path("i").and(warp::get()).then(next_id))
         .or(warp::path("ip")
             .and(warp::path::end())
             .and(warp::post())
             .and(warp::body::json::<ByIP>())
+            .and(tenant())
             .and_then(handle_by_ip))
-        .with(cors);
+        .or(warp::path!("metrics").and(warp::get()).then(metrics))
+        .or(warp::path!("healthz").and(warp::get()).then(healthz))
+        .or(warp::path!("readyz").and(warp::get()).then(readyz))
+        .map(Reply::into_response)
+        .boxed();
+
+    let chat_routes = warp::path!("chats")
+        .and(warp::get())
+        .and(tenant())
+        .then(chats)
+        .or(warp::path!("chats" / i64)
+            .and(warp::get())
+            .and(tenant())
+            .then(chats_from))
+        .or(warp::path!("chat" / String)
+            .and(warp::get())
+            .and(tenant())
+            .then(chat))
+        .or(warp::path!("chat" / String / "export")
+            .and(warp::get())
+            .and(admin())
+            .and(warp::query())
+            .then(chat_export))
+        .or(warp::path!("chat" / String / "similar")
+            .and(warp::get())
+            .and(warp::query())
+            .and(tenant())
+            .then(similar_chats))
+        .or(warp::path!("chat" / String / "reply")
+            .and(warp::post())
+            .and(limited(&REPLY_LIMITER))
+            .and(warp::body::content_length_limit(MAX_QUESTION_BYTES))
+            .and(warp::body::json())
+            .and(client_ip())
+            .and(visitor())
+            .and(tenant())
+            .then(reply))
+        .or(warp::path!("chat" / String / "reply" / "stream")
+            .and(warp::post())
+            .and(limited(&REPLY_LIMITER))
+            .and(warp::body::content_length_limit(MAX_QUESTION_BYTES))
+            .and(warp::body::json())
+            .and(client_ip())
+            .and(visitor())
+            .and(tenant())
+            .then(reply_stream))
+        .or(warp::path!("chat_from" / String / i64)
+            .and(warp::get())
+            .and(tenant())
+            .then(chat_from))
+        .map(Reply::into_response)
+        .boxed();
+
+    let admin_routes = warp::path!("admin")
+        .and(warp::get())
+        .and(warp::query())
+        .and(signed_in())
+        .then(console::index)
+        .or(warp::path!("admin" / "login")
+            .and(warp::post())
+            .and(warp::body::form())
+            .and(client_ip())
+            .then(console::login))
+        .or(warp::path!("admin" / "logout")
+            .and(warp::post())
+            .then(console::logout))
+        .or(warp::path!("admin" / "chat" / String)
+            .and(warp::get())
+            .and(signed_in())
+            .then(console::chat))
+        .or(warp::path!("admin" / "chat" / String / "export")
+            .and(warp::get())
+            .and(warp::query())
+            .and(signed_in())
+            .then(console::export))
+        .or(warp::path!("admin" / "chat" / String / "delete")
+            .and(warp::post())
+            .and(signed_in())
+            .then(console::delete))
+        .or(warp::path!("admin" / "search")
+            .and(warp::get())
+            .and(warp::query())
+            .and(signed_in())
+            .then(console::search))
+        .or(warp::path!("admin" / "visitors")
+            .and(warp::get())
+            .and(warp::query())
+            .and(signed_in())
+            .then(console::visitors_page))
+        .or(warp::path!("admin" / "erase" / String / String)
+            .and(warp::delete())
+            .and(admin())
+            .and(warp::query())
+            .then(erase))
+        .or(warp::path!("admin" / "pseudonym" / String)
+            .and(warp::get())
+            .and(admin())
+            .then(pseudonym))
+        .or(warp::path!("admin" / "visitor" / "merge")
+            .and(warp::post())
+            .and(admin())
+            .and(warp::body::json())
+            .then(merge_visitors))
+        .or(warp::path!("admin" / "visitor" / String)
+            .and(warp::get())
+            .and(admin())
+            .then(visitor_summary))
+        .or(warp::path!("analytics")
+            .and(warp::get())
+            .and(admin())
+            .and(warp::query())
+            .then(analytics))
+        .or(warp::path!("admin" / "faq")
+            .and(warp::post())
+            .and(admin())
+            .and(warp::body::json())
+            .then(run_faq))
+        .or(warp::path!("admin" / "faq")
+            .and(warp::get())
+            .and(admin())
+            .then(latest_faq))
+        .or(warp::path!("admin" / "retention")
+            .and(warp::post())
+            .and(admin())
+            .and(warp::query())
+            .then(retention))
+        .or(warp::path!("admin" / "feed")
+            .and(warp::get())
+            .and(operator())
+            .and(warp::ws())
+            .and(warp::query())
+            .then(feed))
+        .or(warp::path!("admin" / "export")
+            .and(warp::get())
+            .and(admin())
+            .and(warp::query())
+            .then(export))
+        .or(warp::path!("admin" / "import")
+            .and(warp::post())
+            .and(admin())
+            .and(warp::query())
+            .and(warp::body::stream())
+            .then(import))
+        .or(warp::path!("admin" / "ingest")
+            .and(warp::post())
+            .and(admin())
+            .and(tenant())
+            .and(warp::body::stream())
+            .then(ingest))
+        .or(warp::path!("admin" / "kb" / String)
+            .and(warp::put())
+            .and(admin())
+            .and(tenant())
+            .and(warp::body::json())
+            .then(put_document))
+        .or(warp::path!("admin" / "kb" / String)
+            .and(warp::delete())
+            .and(admin())
+            .and(tenant())
+            .then(delete_document))
+        .map(Reply::into_response)
+        .boxed();
+
+    let routes = public_routes
+        .or(chat_routes)
+        .or(admin_routes)
+        .recover(recover)
+        .map(with_request_id)
+        .with(cors)
+        .with(warp::log::custom(observe_http))
+        .with(warp::trace(request_span));
 
     Ok(routes.boxed().into())
 }
@@ -100,31 +344,15 @@ async fn warp(
 // --- HANDLERS ---
 
 async fn i_handler() -> Result<impl warp::Reply, warp::Rejection> {
-    let client = reqwest::Client::new();
     Ok(warp::reply::with_status(
-        next_i(&client).await?.to_string(),
+        next_i().await?.to_string(),
         warp::http::StatusCode::OK,
     ))
 }
 
-async fn next_i(client: &reqwest::Client) -> AppResult<i64> {
-    let res: serde_json::Value = client
-        .get(qdrant_path(&format!("collections/{}/points/1", COLLECTION)).await?)
-        .header(
-            "api-key",
-            SECRETS
-                .lock()
-                .await
-                .get("QDRANT_KEY")
-                .ok_or("QDRANT_KEY not found")
-                .map_err(|e| AppError::new_plain(e))?,
-        )
-        .send()
-        .await
-        .map_err(|e| AppError::new("get_point request", e))?
-        .json()
-        .await
-        .map_err(|e| AppError::new("parse get_point response", e))?;
+async fn next_i() -> AppResult<i64> {
+    let res = qdrant_get(&qdrant_path(&format!("collections/{}/points/1", COLLECTION)).await?)
+        .await?;
 
     let initial_value: i64 = res["result"]["payload"]["u"]
         .as_i64()
@@ -134,179 +362,24 @@ async fn next_i(client: &reqwest::Client) -> AppResult<i64> {
     body.insert("payload".to_string(), json!({ "u": initial_value + 1 }));
     body.insert("points".to_string(), json!("i"));
 
-    client
-        .post(qdrant_path(&format!("collections/{}/points/payload", COLLECTION)).await?)
-        .header(
-            "api-key",
-            SECRETS
-                .lock()
-                .await
-                .get("QDRANT_KEY")
-                .ok_or("QDRANT_KEY not found")
-                .map_err(|e| AppError::new_plain(e))?,
-        )
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| AppError::new("update_point request", e))?;
+    qdrant_post(
+        &qdrant_path(&format!("collections/{}/points/payload", COLLECTION)).await?,
+        body,
+    )
+    .await?;
 
     Ok(initial_value)
 }
 
-async fn handle_search(q: SearchQuery) -> Result<impl warp::Reply, warp::Rejection> {
-    let client = reqwest::Client::new();
-    let mut body = serde_json::Map::new();
-    body.insert(
-        "vector".to_string(),
-        embedding(&q.q)
-            .await
-            .map_err(|e| AppError::new("q to string in handle_search", e))?
-            .into(),
-    );
-    body.insert("limit".to_string(), 7.into());
-    body.insert("with_payload".to_string(), json!(["m", "u"]));
-    if let Some(f) = q.f {
-        println!("saw f: {:#?}", f);
-        let mut must = vec![];
-        for key in f.keys() {
-            if let Some(v) = f.get(key) {
-                must.push(json!({"key": key, "match": {"value": v}}))
-            }
-        }
-        println!("must: {}", serde_json::to_string(&must).unwrap());
-        body.insert("filter".to_string(), json!({"must": must}));
-    }
-    // println!("body: {}", serde_json::to_string(&body).unwrap());
-    let res: serde_json::Value = client
-        .post(qdrant_path(&format!("collections/{}/points/search", COLLECTION)).await?)
-        .header(
-            "api-key",
-            SECRETS
-                .lock()
-                .await
-                .get("QDRANT_KEY")
-                .ok_or("QDRANT_KEY not found in env")
-                .map_err(|e| AppError::new_plain(e))?,
-        )
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| warp::reject::custom(AppError::new("search_points request", e)))?
-        .json()
-        .await
-        .map_err(|e| warp::reject::custom(AppError::new("parse search_points response", e)))?;
-
-    Ok(warp::reply::json(&res["result"]))
-    // println!("{:#?}", res);
-    // Ok(warp::reply::with_status("dir", warp::http::StatusCode::OK))
-}
-
-async fn handle_by_ip(q: ByIP) -> Result<impl warp::Reply, warp::Rejection> {
-    let res: serde_json::Value = reqwest::Client::new()
-        .post(qdrant_path("collections/i/points/query/groups").await?)
-        .header(
-            "api-key",
-            SECRETS
-                .lock()
-                .await
-                .get("QDRANT_KEY")
-                .ok_or("QDRANT_KEY not found in env")
-                .map_err(|e| AppError::new_plain(e))?,
-        )
-        .json(&json!({
-          "group_by": "ip",
-          "limit": 7,
-          "group_size": 1,
-          "order_by": [
-            {
-              "key": "d",
-              "order": "asc"
-            }
-          ],
-          "with_payload": [
-            "ip",
-          ],
-          "filter": {
-            "must": [
-              {
-                "key": "u",
-                "match": {
-                  "value": 1
-                }
-              },
-              {
-                "key": "d",
-                "range": {
-                  "gte": q.d,
-                }
-              }
-            ]
-          }
-        }))
-        .send()
-        .await
-        .map_err(|e| warp::reject::custom(AppError::new("search_points request", e)))?
-        .json()
-        .await
-        .map_err(|e| warp::reject::custom(AppError::new("parse search_points response", e)))?;
-
-    Ok(warp::reply::json(&res["result"]))
-}
-
-async fn handle_group_search(q: GroupSearch) -> Result<impl warp::Reply, warp::Rejection> {
-    let client = reqwest::Client::new();
-    let mut body = serde_json::Map::new();
-    body.insert(
-        "vector".to_string(),
-        embedding(&q.q)
-            .await
-            .map_err(|e| AppError::new("q to string in handle_search", e))?
-            .into(),
-    );
-    body.insert("group_by".to_string(), q.k.into());
-    body.insert("limit".to_string(), 7.into());
-    body.insert("group_size".to_string(), 1.into());
-    body.insert("with_payload".to_string(), json!(["m", "u"]));
-    if let Some(f) = q.f {
-        println!("saw f: {:#?}", f);
-        let mut must = vec![];
-        for key in f.keys() {
-            if let Some(v) = f.get(key) {
-                must.push(json!({"key": key, "match": {"value": v}}))
-            }
-        }
-        println!("must: {}", serde_json::to_string(&must).unwrap());
-        body.insert("filter".to_string(), json!({"must": must}));
-    }
-    // println!("body: {}", serde_json::to_string(&body).unwrap());
-    let res: serde_json::Value = client
-        .post(qdrant_path(&format!("collections/{}/points/search/group", COLLECTION)).await?)
-        .header(
-            "api-key",
-            SECRETS
-                .lock()
-                .await
-                .get("QDRANT_KEY")
-                .ok_or("QDRANT_KEY not found in env")
-                .map_err(|e| AppError::new_plain(e))?,
-        )
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| warp::reject::custom(AppError::new("search_points request", e)))?
-        .json()
-        .await
-        .map_err(|e| warp::reject::custom(AppError::new("parse search_points response", e)))?;
-
-    Ok(warp::reply::json(&res["result"]))
-    // println!("{:#?}", res);
-    // Ok(warp::reply::with_status("dir", warp::http::StatusCode::OK))
-}
-
-async fn handle_get(query: ItemQuery) -> Result<impl warp::Reply, warp::Rejection> {
+async fn handle_get(query: ItemQuery, t: Tenant) -> Result<impl warp::Reply, warp::Rejection> {
     let client = reqwest::Client::new();
 
     match get_point_payload(&client, &query.i).await {
+        // another tenant's item is as good as missing
+        Ok(payload) if !t.owns(&json!({ TENANT_FIELD: payload.o })) => Ok(warp::reply::with_status(
+            warp::reply::json(&"Not Found".to_string()),
+            warp::http::StatusCode::NOT_FOUND,
+        )),
         Ok(payload) => {
             if PRIVATE.contains(&payload.c.as_str()) {
                 if payload.u == query.u.as_str() {
@@ -431,14 +504,6 @@ async fn set(client: &reqwest::Client, s: Set) -> AppResult<()> {
     Ok(())
 }
 
-#[derive(Deserialize)]
-// #[serde(untagged)]
-struct SearchQuery {
-    q: String, // Query string
-    f: Option<std::collections::HashMap<String, serde_json::Value>>, // l: Option<u64>,         // Limit
-                                                                     // r: Option<Vec<String>>, // Attributes to return
-}
-
 #[derive(Deserialize, Serialize, Clone)]
 struct Item {
     u: String,            // User
@@ -466,6 +531,8 @@ struct Payload {
     c: String, //category the point belongs to
     u: String, //user that created it
     v: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    o: Option<String>, //tenant
 }
 
 #[derive(Deserialize)]
@@ -490,15 +557,3 @@ struct Response {
     result: Vec<ResponseResult>,
 }
 
-#[derive(Deserialize)]
-struct GroupSearch {
-    k: String,
-    q: String,
-    f: Option<std::collections::HashMap<String, serde_json::Value>>,
-}
-
-#[derive(Deserialize)]
-struct ByIP {
-    d: i64,
-    p: i64,
-}
@@ -0,0 +1,191 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge_vec, Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec,
+    IntGaugeVec, TextEncoder,
+};
+
+#[cfg(test)]
+mod tests;
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "HTTP requests handled, by route and status",
+        &["route", "status"]
+    )
+    .unwrap()
+});
+
+pub static HTTP_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "HTTP request latency, by route and status",
+        &["route", "status"]
+    )
+    .unwrap()
+});
+
+pub static QDRANT_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "qdrant_request_duration_seconds",
+        "Qdrant request latency, by operation",
+        &["op"]
+    )
+    .unwrap()
+});
+
+pub static QDRANT_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "qdrant_errors_total",
+        "Failed Qdrant requests, by operation and kind of failure",
+        &["op", "kind"]
+    )
+    .unwrap()
+});
+
+pub static EMBEDDING_LATENCY: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "embedding_request_duration_seconds",
+        "Embedding service request latency"
+    )
+    .unwrap()
+});
+
+pub static EMBEDDING_BATCH_SIZE: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "embedding_batch_size",
+        "Number of inputs sent per embedding request",
+        vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0]
+    )
+    .unwrap()
+});
+
+pub static EMBEDDING_CACHE: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "embedding_cache_total",
+        "Embedding cache lookups, by result (hit or miss)",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub static ID_ALLOCATIONS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("id_allocations_total", "IDs handed out by util::id").unwrap()
+});
+
+pub static UPSTREAM_RETRIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "upstream_retries_total",
+        "Retried upstream calls, by upstream",
+        &["upstream"]
+    )
+    .unwrap()
+});
+
+pub static CIRCUIT_OPEN: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "circuit_breaker_open",
+        "1 while the upstream's circuit breaker is open",
+        &["upstream"]
+    )
+    .unwrap()
+});
+
+pub static CIRCUIT_REJECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "circuit_breaker_rejections_total",
+        "Calls failed fast by an open circuit breaker, by upstream",
+        &["upstream"]
+    )
+    .unwrap()
+});
+
+pub static RATE_LIMITED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "rate_limited_total",
+        "Requests refused for exceeding a rate limit, by limiter",
+        &["limiter"]
+    )
+    .unwrap()
+});
+
+/// First path segments of the app's routes.
+const ROUTES: &[&str] = &[
+    "admin",
+    "analytics",
+    "chat",
+    "chat_from",
+    "chats",
+    "facets",
+    "groupsearch",
+    "healthz",
+    "i",
+    "ip",
+    "metrics",
+    "readyz",
+    "recommend",
+    "search",
+];
+/// Segments in the app's deepest route.
+const MAX_SEGMENTS: usize = 4;
+
+/// Collapses a request path into a low-cardinality route label, so `/chat/abc`
+/// and `/chat/def` are both counted under `/chat/:p`. Paths no route could
+/// match, such as probes, are all counted under `other`.
+pub fn route_label(path: &str) -> String {
+    let segments: Vec<&str> = path
+        .trim_matches('/')
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+    match segments.first() {
+        None => "/".to_string(),
+        Some(first) if ROUTES.contains(first) && segments.len() <= MAX_SEGMENTS => segments[1..]
+            .iter()
+            .fold(format!("/{}", first), |acc, _| acc + "/:p"),
+        Some(_) => "other".to_string(),
+    }
+}
+
+/// Collapses a Qdrant URL into an operation label, e.g.
+/// `https://host/collections/i/points/scroll?wait=true` becomes `points/scroll`.
+pub fn qdrant_op(path: &str) -> String {
+    let path = path.split('?').next().unwrap_or_default();
+    let path = match path.find("collections/") {
+        Some(i) => &path[i + "collections/".len()..],
+        None => return "other".to_string(),
+    };
+    let mut segments = path.split('/').skip(1).filter(|s| !s.is_empty());
+    match segments.next() {
+        None => "collection".to_string(),
+        Some(first) => segments.fold(first.to_string(), |acc, s| {
+            if s.chars().any(|c| c.is_ascii_digit()) {
+                acc + "/:id"
+            } else {
+                acc + "/" + s
+            }
+        }),
+    }
+}
+
+pub fn observe_http(info: warp::log::Info) {
+    let route = route_label(info.path());
+    let status = info.status().as_u16().to_string();
+    HTTP_REQUESTS.with_label_values(&[&route, &status]).inc();
+    HTTP_LATENCY
+        .with_label_values(&[&route, &status])
+        .observe(info.elapsed().as_secs_f64());
+}
+
+pub fn render() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap_or_default();
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+pub async fn metrics() -> impl warp::Reply {
+    warp::reply::with_header(render(), "Content-Type", "text/plain; version=0.0.4")
+}
@@ -0,0 +1,53 @@
+use super::{qdrant_op, route_label};
+
+#[test]
+fn ids_collapse_into_one_route() {
+    assert_eq!(route_label("/chat/abc"), "/chat/:p");
+    assert_eq!(route_label("/chat/def/reply/stream"), "/chat/:p/:p/:p");
+    assert_eq!(route_label("/admin/kb/doc-1"), "/admin/:p/:p");
+}
+
+#[test]
+fn root_keeps_its_own_label() {
+    assert_eq!(route_label("/"), "/");
+    assert_eq!(route_label(""), "/");
+}
+
+#[test]
+fn unknown_and_overlong_paths_are_other() {
+    assert_eq!(route_label("/wp-login.php"), "other");
+    assert_eq!(route_label("/.env"), "other");
+    assert_eq!(route_label("/chat/a/b/c/d"), "other");
+}
+
+#[test]
+fn qdrant_urls_collapse_into_operations() {
+    assert_eq!(
+        qdrant_op("https://host:6333/collections/i/points/scroll?wait=true"),
+        "points/scroll"
+    );
+    assert_eq!(
+        qdrant_op("https://host:6333/collections/i/points/search/groups"),
+        "points/search/groups"
+    );
+    assert_eq!(qdrant_op("https://host:6333/collections/i"), "collection");
+    assert_eq!(qdrant_op("https://host:6333/collections/i/"), "collection");
+}
+
+#[test]
+fn qdrant_point_ids_are_collapsed() {
+    assert_eq!(
+        qdrant_op("https://host/collections/i/points/42"),
+        "points/:id"
+    );
+    assert_eq!(
+        qdrant_op("https://host/collections/i/points/0b7e3c1a-9f0e-5d1c-8a7b-2f3e4d5c6b7a"),
+        "points/:id"
+    );
+}
+
+#[test]
+fn non_collection_urls_are_other() {
+    assert_eq!(qdrant_op("https://host:6333/healthz"), "other");
+    assert_eq!(qdrant_op(""), "other");
+}
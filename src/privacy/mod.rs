@@ -0,0 +1,79 @@
+//! Client address pseudonymization. With an `IP_HMAC_KEY` secret, addresses
+//! are stored as a keyed HMAC-SHA256 instead of verbatim, so the same visitor
+//! still groups together but the address can only be recovered by someone
+//! holding the key and a guess. `IP_TRUNCATE=true` zeroes the last IPv4
+//! octet, or everything past the IPv6 /64, before hashing or storing.
+
+use std::net::IpAddr;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::constants::SECRETS;
+
+/// Prefix marking a stored address as a pseudonym.
+pub const PSEUDONYM_PREFIX: &str = "h:";
+
+/// Hex HMAC-SHA256 of `data` under `key`.
+pub fn hmac_hex(key: &str, data: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Clone, Default)]
+pub struct Config {
+    pub key: Option<String>,
+    pub truncate: bool,
+}
+
+pub async fn config() -> Config {
+    let secrets = SECRETS.lock().await;
+    Config {
+        key: secrets.get("IP_HMAC_KEY").filter(|k| !k.is_empty()),
+        truncate: secrets.get("IP_TRUNCATE").as_deref() == Some("true"),
+    }
+}
+
+/// `ip` with the host part dropped: the last IPv4 octet, or the low 64 bits
+/// of an IPv6 address.
+pub fn truncate(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            IpAddr::from([a, b, c, 0])
+        }
+        IpAddr::V6(v6) => {
+            let mut segments = v6.segments();
+            segments[4..].fill(0);
+            IpAddr::from(segments)
+        }
+    }
+}
+
+impl Config {
+    /// The form `address` is stored in. Anything that isn't an IP address,
+    /// such as an already pseudonymized value, passes through unchanged.
+    pub fn pseudonymize(&self, address: &str) -> String {
+        let Ok(mut ip) = address.trim().parse::<IpAddr>() else {
+            return address.to_string();
+        };
+        if self.truncate {
+            ip = truncate(ip);
+        }
+        let Some(key) = &self.key else {
+            return ip.to_string();
+        };
+        format!("{}{}", PSEUDONYM_PREFIX, hmac_hex(key, &ip.to_string()))
+    }
+}
+
+/// Shorthand for pseudonymizing a single address with the current config.
+pub async fn pseudonymize(address: &str) -> String {
+    config().await.pseudonymize(address)
+}
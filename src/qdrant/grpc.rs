@@ -0,0 +1,672 @@
+//! gRPC transport. Requests are still built as REST paths and JSON bodies all
+//! over the crate; this module translates the ones it understands into Qdrant
+//! gRPC calls and turns the responses back into the JSON REST would have
+//! returned, so callers can't tell which transport served them. Anything it
+//! can't translate exactly (vectors in responses, ordering, named vectors, …)
+//! is left to REST.
+
+use qdrant_client::{
+    qdrant::{
+        point_id::PointIdOptions, points_selector::PointsSelectorOneOf, with_payload_selector,
+        Condition, CountPointsBuilder, DeletePointsBuilder, Filter, GetPointsBuilder,
+        PayloadIncludeSelector, PointGroup, PointId, PointStruct, PointsIdsList, Range,
+        RetrievedPoint, ScoredPoint, ScrollPointsBuilder, SearchPointGroupsBuilder,
+        SearchPointsBuilder, SetPayloadPointsBuilder, UpdateResult, UpdateStatus,
+        UpsertPointsBuilder,
+    },
+    Payload, Qdrant, QdrantError,
+};
+use serde_json::{json, Map, Value};
+use tokio::sync::OnceCell;
+
+use crate::{
+    app::{AppError, AppResult},
+    constants::SECRETS,
+    resilience::Failure,
+};
+
+static CLIENT: OnceCell<Qdrant> = OnceCell::const_new();
+
+/// A REST request the gRPC transport knows how to serve.
+#[derive(Debug, Clone)]
+pub enum Request {
+    Upsert {
+        collection: String,
+        points: Vec<(PointId, Vec<f32>, Payload)>,
+        wait: bool,
+    },
+    Retrieve {
+        collection: String,
+        ids: Vec<PointId>,
+        with_payload: with_payload_selector::SelectorOptions,
+    },
+    Search {
+        collection: String,
+        vector: Vec<f32>,
+        limit: u64,
+        offset: Option<u64>,
+        filter: Option<Filter>,
+        with_payload: with_payload_selector::SelectorOptions,
+        score_threshold: Option<f32>,
+    },
+    SearchGroups {
+        collection: String,
+        vector: Vec<f32>,
+        group_by: String,
+        limit: u32,
+        group_size: u32,
+        filter: Option<Filter>,
+        with_payload: with_payload_selector::SelectorOptions,
+    },
+    Scroll {
+        collection: String,
+        limit: Option<u32>,
+        offset: Option<PointId>,
+        filter: Option<Filter>,
+        with_payload: with_payload_selector::SelectorOptions,
+    },
+    SetPayload {
+        collection: String,
+        payload: Payload,
+        selector: PointsSelectorOneOf,
+        wait: bool,
+    },
+    Delete {
+        collection: String,
+        selector: PointsSelectorOneOf,
+        wait: bool,
+    },
+    Count {
+        collection: String,
+        filter: Option<Filter>,
+        exact: bool,
+    },
+}
+
+/// Whether `QDRANT_TRANSPORT=grpc` is configured.
+pub async fn enabled() -> bool {
+    SECRETS.lock().await.get("QDRANT_TRANSPORT").as_deref() == Some("grpc")
+}
+
+/// `QDRANT_GRPC_URL`, or `QDRANT_URL` with the gRPC port (6334) swapped in.
+async fn url() -> AppResult<String> {
+    let secrets = SECRETS.lock().await;
+    if let Some(url) = secrets.get("QDRANT_GRPC_URL") {
+        return Ok(url);
+    }
+    let rest = secrets
+        .get("QDRANT_URL")
+        .ok_or(AppError::new_plain("QDRANT_URL not found in env"))?;
+    let rest = rest.trim_end_matches('/');
+    let authority_start = rest.find("://").map_or(0, |i| i + 3);
+    Ok(match rest[authority_start..].rfind(':') {
+        Some(i) => format!("{}:6334", &rest[..authority_start + i]),
+        None => format!("{}:6334", rest),
+    })
+}
+
+async fn client() -> AppResult<&'static Qdrant> {
+    CLIENT
+        .get_or_try_init(|| async {
+            let key = SECRETS.lock().await.get("QDRANT_KEY");
+            Qdrant::from_url(&url().await?)
+                .api_key(key)
+                .build()
+                .map_err(|e| AppError::new("building qdrant grpc client", e))
+        })
+        .await
+}
+
+/// Splits `.../collections/{name}/{op}?{query}` into its collection, operation
+/// and whether `wait=true` was asked for.
+fn split(path: &str) -> Option<(String, String, bool)> {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let rest = &path[path.find("collections/")? + "collections/".len()..];
+    let (collection, op) = rest.split_once('/')?;
+    let wait = query.split('&').any(|p| p == "wait=true" || p == "wait");
+    Some((
+        collection.to_string(),
+        op.trim_end_matches('/').to_string(),
+        wait,
+    ))
+}
+
+/// Fails unless every key of `body` is one we translate; an unknown option
+/// would otherwise be dropped silently.
+fn only(body: &Value, keys: &[&str]) -> Option<()> {
+    body.as_object()?
+        .keys()
+        .all(|k| keys.contains(&k.as_str()))
+        .then_some(())
+}
+
+fn point_id(v: &Value) -> Option<PointId> {
+    match v {
+        Value::String(s) => Some(PointId::from(s.clone())),
+        Value::Number(n) => Some(PointId::from(n.as_u64()?)),
+        _ => None,
+    }
+}
+
+fn point_ids(v: &Value) -> Option<Vec<PointId>> {
+    v.as_array()?.iter().map(point_id).collect()
+}
+
+fn vector(v: &Value) -> Option<Vec<f32>> {
+    v.as_array()?
+        .iter()
+        .map(|x| x.as_f64().map(|x| x as f32))
+        .collect()
+}
+
+fn payload(v: &Value) -> Option<Payload> {
+    Payload::try_from(v.clone()).ok()
+}
+
+/// REST defaults differ by endpoint, so the caller says what "absent" means.
+fn with_payload(
+    v: Option<&Value>,
+    default: bool,
+) -> Option<with_payload_selector::SelectorOptions> {
+    match v {
+        None => Some(with_payload_selector::SelectorOptions::Enable(default)),
+        Some(Value::Bool(b)) => Some(with_payload_selector::SelectorOptions::Enable(*b)),
+        Some(Value::Array(fields)) => Some(with_payload_selector::SelectorOptions::Include(
+            PayloadIncludeSelector {
+                fields: fields
+                    .iter()
+                    .map(|f| f.as_str().map(str::to_string))
+                    .collect::<Option<_>>()?,
+            },
+        )),
+        Some(_) => None,
+    }
+}
+
+fn conditions(v: &Value) -> Option<Vec<Condition>> {
+    match v {
+        Value::Array(cs) => cs.iter().map(condition).collect(),
+        Value::Object(_) => Some(vec![condition(v)?]),
+        _ => None,
+    }
+}
+
+fn condition(v: &Value) -> Option<Condition> {
+    let o = v.as_object()?;
+    if let Some(key) = o.get("key").and_then(Value::as_str) {
+        if let Some(m) = o.get("match") {
+            only(v, &["key", "match"])?;
+            return match (m.get("value"), m.get("any")) {
+                (Some(Value::String(s)), None) => Some(Condition::matches(key, s.clone())),
+                (Some(Value::Bool(b)), None) => Some(Condition::matches(key, *b)),
+                (Some(Value::Number(n)), None) => Some(Condition::matches(key, n.as_i64()?)),
+                (None, Some(Value::Array(any))) => {
+                    if let Some(s) = any
+                        .iter()
+                        .map(|x| x.as_str().map(str::to_string))
+                        .collect::<Option<Vec<_>>>()
+                    {
+                        Some(Condition::matches(key, s))
+                    } else {
+                        let i = any.iter().map(Value::as_i64).collect::<Option<Vec<_>>>()?;
+                        Some(Condition::matches(key, i))
+                    }
+                }
+                _ => None,
+            };
+        }
+        if let Some(r) = o.get("range") {
+            only(v, &["key", "range"])?;
+            only(r, &["gt", "gte", "lt", "lte"])?;
+            // a bound gRPC can't express (e.g. a datetime string) is left to REST
+            // rather than dropped
+            let bound = |b: &str| match r.get(b) {
+                None | Some(Value::Null) => Some(None),
+                Some(b) => b.as_f64().map(Some),
+            };
+            return Some(Condition::range(
+                key,
+                Range {
+                    gt: bound("gt")?,
+                    gte: bound("gte")?,
+                    lt: bound("lt")?,
+                    lte: bound("lte")?,
+                },
+            ));
+        }
+        return None;
+    }
+    if let Some(e) = o.get("is_empty") {
+        only(v, &["is_empty"])?;
+        return Some(Condition::is_empty(e.get("key")?.as_str()?));
+    }
+    if let Some(e) = o.get("is_null") {
+        only(v, &["is_null"])?;
+        return Some(Condition::is_null(e.get("key")?.as_str()?));
+    }
+    if let Some(ids) = o.get("has_id") {
+        only(v, &["has_id"])?;
+        return Some(Condition::has_id(point_ids(ids)?));
+    }
+    Some(Condition::from(filter(v)?))
+}
+
+pub fn filter(v: &Value) -> Option<Filter> {
+    only(v, &["must", "should", "must_not"])?;
+    Some(Filter {
+        must: v.get("must").map(conditions).unwrap_or(Some(vec![]))?,
+        should: v.get("should").map(conditions).unwrap_or(Some(vec![]))?,
+        must_not: v.get("must_not").map(conditions).unwrap_or(Some(vec![]))?,
+        ..Default::default()
+    })
+}
+
+fn optional_filter(body: &Value) -> Option<Option<Filter>> {
+    optional(body, "filter", filter)
+}
+
+/// Reads an optional field with `read`. A field that is present but can't be
+/// read gives up on the translation rather than being dropped.
+fn optional<T>(body: &Value, key: &str, read: impl Fn(&Value) -> Option<T>) -> Option<Option<T>> {
+    match body.get(key) {
+        None | Some(Value::Null) => Some(None),
+        Some(v) => Some(Some(read(v)?)),
+    }
+}
+
+fn selector(body: &Value) -> Option<PointsSelectorOneOf> {
+    match (body.get("points"), body.get("filter")) {
+        (Some(ids), None) => Some(PointsIdsList::from(point_ids(ids)?).into()),
+        (None, Some(f)) => Some(filter(f)?.into()),
+        _ => None,
+    }
+}
+
+/// Maps a REST method, path and body onto a gRPC request, or `None` when REST
+/// should serve it.
+pub fn translate(method: &reqwest::Method, path: &str, body: &Value) -> Option<Request> {
+    let (collection, op, wait) = split(path)?;
+    match (method.as_str(), op.as_str()) {
+        ("PUT", "points") => {
+            only(body, &["points"])?;
+            let points = body["points"]
+                .as_array()?
+                .iter()
+                .map(|p| {
+                    only(p, &["id", "vector", "payload"])?;
+                    Some((
+                        point_id(&p["id"])?,
+                        vector(&p["vector"])?,
+                        match p.get("payload") {
+                            None | Some(Value::Null) => Payload::new(),
+                            Some(v) => payload(v)?,
+                        },
+                    ))
+                })
+                .collect::<Option<_>>()?;
+            Some(Request::Upsert {
+                collection,
+                points,
+                wait,
+            })
+        }
+        ("POST", "points") => {
+            only(body, &["ids", "with_payload", "with_vector"])?;
+            if body
+                .get("with_vector")
+                .is_some_and(|v| v != &Value::Bool(false))
+            {
+                return None;
+            }
+            Some(Request::Retrieve {
+                collection,
+                ids: point_ids(body.get("ids")?)?,
+                with_payload: with_payload(body.get("with_payload"), true)?,
+            })
+        }
+        ("POST", "points/search") => {
+            only(
+                body,
+                &[
+                    "vector",
+                    "limit",
+                    "offset",
+                    "filter",
+                    "with_payload",
+                    "score_threshold",
+                ],
+            )?;
+            Some(Request::Search {
+                collection,
+                vector: vector(body.get("vector")?)?,
+                limit: body.get("limit")?.as_u64()?,
+                offset: optional(body, "offset", Value::as_u64)?,
+                filter: optional_filter(body)?,
+                with_payload: with_payload(body.get("with_payload"), false)?,
+                score_threshold: optional(body, "score_threshold", Value::as_f64)?
+                    .map(|s| s as f32),
+            })
+        }
+        ("POST", "points/search/groups") => {
+            only(
+                body,
+                &[
+                    "vector",
+                    "group_by",
+                    "limit",
+                    "group_size",
+                    "filter",
+                    "with_payload",
+                ],
+            )?;
+            Some(Request::SearchGroups {
+                collection,
+                vector: vector(body.get("vector")?)?,
+                group_by: body.get("group_by")?.as_str()?.to_string(),
+                limit: body.get("limit")?.as_u64()? as u32,
+                group_size: body.get("group_size")?.as_u64()? as u32,
+                filter: optional_filter(body)?,
+                with_payload: with_payload(body.get("with_payload"), false)?,
+            })
+        }
+        ("POST", "points/scroll") => {
+            only(body, &["limit", "offset", "filter", "with_payload"])?;
+            Some(Request::Scroll {
+                collection,
+                limit: optional(body, "limit", Value::as_u64)?.map(|l| l as u32),
+                offset: optional(body, "offset", point_id)?,
+                filter: optional_filter(body)?,
+                with_payload: with_payload(body.get("with_payload"), true)?,
+            })
+        }
+        ("POST", "points/payload") => {
+            only(body, &["payload", "points", "filter"])?;
+            Some(Request::SetPayload {
+                collection,
+                payload: payload(body.get("payload")?)?,
+                selector: selector(body)?,
+                wait,
+            })
+        }
+        ("POST", "points/delete") => {
+            only(body, &["points", "filter"])?;
+            Some(Request::Delete {
+                collection,
+                selector: selector(body)?,
+                wait,
+            })
+        }
+        ("POST", "points/count") => {
+            only(body, &["filter", "exact"])?;
+            Some(Request::Count {
+                collection,
+                filter: optional_filter(body)?,
+                exact: optional(body, "exact", Value::as_bool)?.unwrap_or(true),
+            })
+        }
+        _ => None,
+    }
+}
+
+fn point_id_json(id: Option<PointId>) -> Value {
+    match id.and_then(|id| id.point_id_options) {
+        Some(PointIdOptions::Num(n)) => json!(n),
+        Some(PointIdOptions::Uuid(u)) => json!(u),
+        None => Value::Null,
+    }
+}
+
+fn payload_json(payload: std::collections::HashMap<String, qdrant_client::qdrant::Value>) -> Value {
+    if payload.is_empty() {
+        Value::Null
+    } else {
+        Value::from(Payload::from(payload))
+    }
+}
+
+fn scored_json(p: ScoredPoint) -> Value {
+    json!({
+        "id": point_id_json(p.id),
+        "version": p.version,
+        "score": p.score,
+        "payload": payload_json(p.payload),
+        "vector": null,
+    })
+}
+
+fn retrieved_json(p: RetrievedPoint) -> Value {
+    json!({
+        "id": point_id_json(p.id),
+        "payload": payload_json(p.payload),
+        "vector": null,
+    })
+}
+
+fn group_json(g: PointGroup) -> Value {
+    use qdrant_client::qdrant::group_id::Kind;
+    json!({
+        "id": match g.id.and_then(|id| id.kind) {
+            Some(Kind::UnsignedValue(v)) => json!(v),
+            Some(Kind::IntegerValue(v)) => json!(v),
+            Some(Kind::StringValue(v)) => json!(v),
+            None => Value::Null,
+        },
+        "hits": g.hits.into_iter().map(scored_json).collect::<Vec<_>>(),
+    })
+}
+
+fn update_json(r: Option<UpdateResult>) -> Value {
+    match r {
+        Some(r) => json!({
+            "operation_id": r.operation_id,
+            "status": UpdateStatus::try_from(r.status)
+                .map(|s| s.as_str_name().to_lowercase())
+                .unwrap_or_default(),
+        }),
+        None => Value::Null,
+    }
+}
+
+fn envelope(result: Value, time: f64) -> Value {
+    json!({"result": result, "status": "ok", "time": time})
+}
+
+/// Statuses that mean the server may answer the same call later.
+fn failure(m: &str, e: QdrantError) -> Failure {
+    let transient = match &e {
+        QdrantError::ResponseError { status } => matches!(
+            status.code(),
+            tonic::Code::Unavailable
+                | tonic::Code::DeadlineExceeded
+                | tonic::Code::ResourceExhausted
+                | tonic::Code::Aborted
+        ),
+        QdrantError::ResourceExhaustedError { .. } => true,
+        _ => false,
+    };
+    Failure {
+        error: AppError::new(m, e),
+        transient,
+    }
+}
+
+/// Runs a translated request and shapes the response like REST's.
+pub async fn execute(request: Request) -> Result<Value, Failure> {
+    let client = client().await.map_err(Failure::transient)?;
+    match request {
+        Request::Upsert {
+            collection,
+            points,
+            wait,
+        } => {
+            let points: Vec<PointStruct> = points
+                .into_iter()
+                .map(|(id, vector, payload)| PointStruct::new(id, vector, payload))
+                .collect();
+            let r = client
+                .upsert_points(UpsertPointsBuilder::new(collection, points).wait(wait))
+                .await
+                .map_err(|e| failure("grpc upsert_points", e))?;
+            Ok(envelope(update_json(r.result), r.time))
+        }
+        Request::Retrieve {
+            collection,
+            ids,
+            with_payload,
+        } => {
+            let r = client
+                .get_points(
+                    GetPointsBuilder::new(collection, ids)
+                        .with_payload(with_payload)
+                        .with_vectors(false),
+                )
+                .await
+                .map_err(|e| failure("grpc get_points", e))?;
+            Ok(envelope(
+                r.result.into_iter().map(retrieved_json).collect(),
+                r.time,
+            ))
+        }
+        Request::Search {
+            collection,
+            vector,
+            limit,
+            offset,
+            filter,
+            with_payload,
+            score_threshold,
+        } => {
+            let mut b =
+                SearchPointsBuilder::new(collection, vector, limit).with_payload(with_payload);
+            if let Some(o) = offset {
+                b = b.offset(o);
+            }
+            if let Some(f) = filter {
+                b = b.filter(f);
+            }
+            if let Some(s) = score_threshold {
+                b = b.score_threshold(s);
+            }
+            let r = client
+                .search_points(b)
+                .await
+                .map_err(|e| failure("grpc search_points", e))?;
+            Ok(envelope(
+                r.result.into_iter().map(scored_json).collect(),
+                r.time,
+            ))
+        }
+        Request::SearchGroups {
+            collection,
+            vector,
+            group_by,
+            limit,
+            group_size,
+            filter,
+            with_payload,
+        } => {
+            let mut b =
+                SearchPointGroupsBuilder::new(collection, vector, limit, group_by, group_size)
+                    .with_payload(with_payload);
+            if let Some(f) = filter {
+                b = b.filter(f);
+            }
+            let r = client
+                .search_groups(b)
+                .await
+                .map_err(|e| failure("grpc search_groups", e))?;
+            Ok(envelope(
+                json!({"groups": r.result.map(|g| g.groups).unwrap_or_default().into_iter().map(group_json).collect::<Vec<_>>()}),
+                r.time,
+            ))
+        }
+        Request::Scroll {
+            collection,
+            limit,
+            offset,
+            filter,
+            with_payload,
+        } => {
+            let mut b = ScrollPointsBuilder::new(collection)
+                .with_payload(with_payload)
+                .with_vectors(false);
+            if let Some(l) = limit {
+                b = b.limit(l);
+            }
+            if let Some(o) = offset {
+                b = b.offset(o);
+            }
+            if let Some(f) = filter {
+                b = b.filter(f);
+            }
+            let r = client
+                .scroll(b)
+                .await
+                .map_err(|e| failure("grpc scroll", e))?;
+            Ok(envelope(
+                json!({
+                    "points": r.result.into_iter().map(retrieved_json).collect::<Vec<_>>(),
+                    "next_page_offset": point_id_json(r.next_page_offset),
+                }),
+                r.time,
+            ))
+        }
+        Request::SetPayload {
+            collection,
+            payload,
+            selector,
+            wait,
+        } => {
+            let r = client
+                .set_payload(
+                    SetPayloadPointsBuilder::new(collection, payload)
+                        .points_selector(selector)
+                        .wait(wait),
+                )
+                .await
+                .map_err(|e| failure("grpc set_payload", e))?;
+            Ok(envelope(update_json(r.result), r.time))
+        }
+        Request::Delete {
+            collection,
+            selector,
+            wait,
+        } => {
+            let r = client
+                .delete_points(
+                    DeletePointsBuilder::new(collection)
+                        .points(selector)
+                        .wait(wait),
+                )
+                .await
+                .map_err(|e| failure("grpc delete_points", e))?;
+            Ok(envelope(update_json(r.result), r.time))
+        }
+        Request::Count {
+            collection,
+            filter,
+            exact,
+        } => {
+            let mut b = CountPointsBuilder::new(collection).exact(exact);
+            if let Some(f) = filter {
+                b = b.filter(f);
+            }
+            let r = client
+                .count(b)
+                .await
+                .map_err(|e| failure("grpc count", e))?;
+            Ok(envelope(
+                json!({"count": r.result.map(|c| c.count).unwrap_or_default()}),
+                r.time,
+            ))
+        }
+    }
+}
+
+/// Lists a body's top-level keys, for logging requests left to REST.
+pub fn describe(body: &Value) -> String {
+    body.as_object()
+        .map(Map::keys)
+        .map(|k| k.cloned().collect::<Vec<_>>().join(","))
+        .unwrap_or_default()
+}
@@ -1,6 +1,21 @@
+use std::{collections::HashSet, sync::Mutex, time::Instant};
+
+use once_cell::sync::Lazy;
+
 use serde::Serialize;
+use serde_json::json;
+use tracing::Instrument;
 
-use crate::{app::{AppResult, AppError}, constants::SECRETS};
+pub mod grpc;
+#[cfg(test)]
+mod tests;
+
+use crate::{
+    app::{AppError, AppResult},
+    constants::{QDRANT_READ_TIMEOUT, QDRANT_RETRIES, QDRANT_WRITE_TIMEOUT, SECRETS},
+    metrics::{qdrant_op, QDRANT_ERRORS, QDRANT_LATENCY},
+    resilience::{call, Failure, Policy, QDRANT_BREAKER},
+};
 
 pub async fn qdrant_path(path: &str) -> AppResult<String> {
     Ok(format!(
@@ -10,51 +25,199 @@ pub async fn qdrant_path(path: &str) -> AppResult<String> {
             .await
             .get("QDRANT_URL")
             .ok_or("QDRANT_KEY not found in env")
-            .map_err(|e| AppError::new_plain(e))?,
+            .map_err(AppError::new_plain)?,
         path
     ))
 }
 
-pub async fn qdrant_put(path: &str, body: impl Serialize) -> AppResult<serde_json::Value> {
-    Ok(reqwest::Client::new()
-        .put(path)
-        .header(
-            "api-key",
-            SECRETS
-                .lock()
-                .await
-                .get("QDRANT_KEY")
-                .ok_or("QDRANT_KEY not found in env")
-                .map_err(|e| AppError::new_plain(e))?,
-        )
-        .header("Content-Type", "application/json")
-        .json(&body)
-        .send()
+async fn api_key() -> AppResult<String> {
+    SECRETS
+        .lock()
         .await
-        .map_err(|e| AppError::new("upsert_points", e))?
-        .json()
-        .await
-        .map_err(|e| AppError::new("qdrant response to json", e))?)
+        .get("QDRANT_KEY")
+        .ok_or("QDRANT_KEY not found in env")
+        .map_err(AppError::new_plain)
+}
+
+/// Picks the timeout and retry policy for a Qdrant operation. Reads, upserts
+/// of named points and index or collection creation are safe to replay.
+/// Payload merges, batches of them and deletes are not: a retry may land
+/// after a later write and undo it, or, by filter, hit points written in
+/// between. Writes just get
+/// longer to finish.
+fn policy(method: &reqwest::Method, op: &str) -> Policy {
+    // `POST points` retrieves, `PUT points` upserts
+    let write = *method != reqwest::Method::GET
+        && matches!(
+            (method.as_str(), op),
+            ("PUT", "points")
+                | (_, "points/payload")
+                | (_, "points/payload/delete")
+                | (_, "points/batch")
+                | (_, "points/delete")
+                | (_, "points/vectors")
+                | (_, "index")
+        );
+    Policy {
+        timeout: if write {
+            QDRANT_WRITE_TIMEOUT
+        } else {
+            QDRANT_READ_TIMEOUT
+        },
+        retries: QDRANT_RETRIES,
+        idempotent: !matches!(
+            op,
+            "points/payload" | "points/payload/delete" | "points/batch" | "points/delete"
+        ),
+    }
+}
+
+/// Sends a request to Qdrant over the configured transport and records its
+/// latency and outcome under the operation derived from `path`. A failed call
+/// is counted under the kind of its last failed attempt: the HTTP status, or
+/// `transport`, `decode` or `grpc`; `other` covers timeouts and an open
+/// circuit.
+async fn send(
+    method: reqwest::Method,
+    path: &str,
+    body: Option<serde_json::Value>,
+) -> AppResult<serde_json::Value> {
+    let op = qdrant_op(path);
+    let translated = match &body {
+        Some(b) if grpc::enabled().await => {
+            let translated = grpc::translate(&method, path, b);
+            if translated.is_none() {
+                tracing::debug!(%op, keys = %grpc::describe(b), "not translatable to grpc, using rest");
+            }
+            translated
+        }
+        _ => None,
+    };
+    let policy = policy(&method, &op);
+    let start = Instant::now();
+    let failed = Mutex::new("other".to_string());
+    let fail = |kind: &str| *failed.lock().unwrap() = kind.to_string();
+    let res = match translated {
+        Some(request) => {
+            call(&QDRANT_BREAKER, policy, || async {
+                grpc::execute(request.clone())
+                    .await
+                    .inspect_err(|_| fail("grpc"))
+            })
+            .instrument(tracing::info_span!("qdrant", op = %op, transport = "grpc"))
+            .await
+        }
+        None => {
+            let mut request = reqwest::Client::new()
+                .request(method, path)
+                .header("api-key", api_key().await?);
+            if let Some(b) = &body {
+                request = request.header("Content-Type", "application/json").json(b);
+            }
+            call(&QDRANT_BREAKER, policy, || async {
+                let res = request
+                    .try_clone()
+                    .ok_or(Failure::permanent(AppError::new_plain(
+                        "qdrant request body cannot be replayed",
+                    )))?
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        fail("transport");
+                        Failure::from_reqwest(&format!("qdrant {}", op), e)
+                    })?;
+                let status = res.status();
+                if !status.is_success() {
+                    fail(status.as_str());
+                    let body = res.text().await.unwrap_or_default();
+                    return Err(Failure::from_status(
+                        &format!("qdrant {}", op),
+                        status,
+                        &body,
+                    ));
+                }
+                res.json().await.map_err(|e| {
+                    fail("decode");
+                    Failure::permanent(AppError::new("qdrant response to json", e))
+                })
+            })
+            .instrument(tracing::info_span!("qdrant", op = %op, transport = "rest"))
+            .await
+        }
+    };
+    QDRANT_LATENCY
+        .with_label_values(&[&op])
+        .observe(start.elapsed().as_secs_f64());
+    if res.is_err() {
+        let kind = failed.lock().unwrap().clone();
+        QDRANT_ERRORS.with_label_values(&[&op, &kind]).inc();
+    }
+    res
+}
+
+fn to_json(body: impl Serialize) -> AppResult<serde_json::Value> {
+    serde_json::to_value(body).map_err(|e| AppError::new("qdrant request to json", e))
+}
+
+pub async fn qdrant_get(path: &str) -> AppResult<serde_json::Value> {
+    send(reqwest::Method::GET, path, None).await
+}
+
+pub async fn qdrant_put(path: &str, body: impl Serialize) -> AppResult<serde_json::Value> {
+    send(reqwest::Method::PUT, path, Some(to_json(body)?)).await
 }
 
 pub async fn qdrant_post(path: &str, body: impl Serialize) -> AppResult<serde_json::Value> {
-    Ok(reqwest::Client::new()
-        .post(path)
-        .header(
-            "api-key",
-            SECRETS
-                .lock()
-                .await
-                .get("QDRANT_KEY")
-                .ok_or("QDRANT_KEY not found in env")
-                .map_err(|e| AppError::new_plain(e))?,
-        )
-        .header("Content-Type", "application/json")
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| AppError::new("upsert_points", e))?
-        .json()
-        .await
-        .map_err(|e| AppError::new("qdrant response to json", e))?)
-}
\ No newline at end of file
+    send(reqwest::Method::POST, path, Some(to_json(body)?)).await
+}
+
+/// Collections this process has already made sure exist.
+static KNOWN_COLLECTIONS: Lazy<Mutex<HashSet<String>>> = Lazy::new(Default::default);
+
+/// Creates collection `name` without vectors, for records that are only ever
+/// looked up by ID or filter, unless it already exists.
+pub async fn vectorless_collection(name: &str) -> AppResult<()> {
+    if KNOWN_COLLECTIONS.lock().unwrap().contains(name) {
+        return Ok(());
+    }
+    let path = qdrant_path(&format!("collections/{}", name)).await?;
+    let exists = qdrant_get(&format!("{}/exists", path)).await?["result"]["exists"]
+        .as_bool()
+        .unwrap_or(false);
+    if !exists {
+        qdrant_put(&path, json!({ "vectors": {} })).await?;
+    }
+    KNOWN_COLLECTIONS.lock().unwrap().insert(name.to_string());
+    Ok(())
+}
+
+/// Payload indexes, by collection and field, this process has already made
+/// sure exist.
+static KNOWN_INDEXES: Lazy<Mutex<HashSet<(String, String)>>> = Lazy::new(Default::default);
+
+/// Creates a keyword index on payload `field` of `collection`, which facets
+/// need, unless this process already did. Qdrant accepts creating an index
+/// that exists.
+pub async fn keyword_index(collection: &str, field: &str) -> AppResult<()> {
+    payload_index(collection, field, "keyword").await
+}
+
+/// Creates an integer index on payload `field` of `collection`, which
+/// ordering a scroll by the field needs, like `keyword_index`.
+pub async fn integer_index(collection: &str, field: &str) -> AppResult<()> {
+    payload_index(collection, field, "integer").await
+}
+
+async fn payload_index(collection: &str, field: &str, schema: &str) -> AppResult<()> {
+    let key = (collection.to_string(), field.to_string());
+    if KNOWN_INDEXES.lock().unwrap().contains(&key) {
+        return Ok(());
+    }
+    qdrant_put(
+        &qdrant_path(&format!("collections/{}/index?wait=true", collection)).await?,
+        json!({"field_name": field, "field_schema": schema}),
+    )
+    .await?;
+    KNOWN_INDEXES.lock().unwrap().insert(key);
+    Ok(())
+}
@@ -0,0 +1,1119 @@
+//! Sends the same requests over REST and over gRPC to one in-memory mock that
+//! speaks both APIs, and checks callers get the same answers either way, and
+//! that requests gRPC can't express fall back to REST.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc, Mutex,
+    },
+};
+
+use once_cell::sync::Lazy;
+use qdrant_client::{
+    qdrant::{
+        self as q, condition::ConditionOneOf, group_id, point_id::PointIdOptions,
+        points_selector::PointsSelectorOneOf, points_server::Points, points_server::PointsServer,
+        r#match::MatchValue, vector, vectors::VectorsOptions,
+        with_payload_selector::SelectorOptions,
+    },
+    Payload,
+};
+use serde_json::{json, Map, Value};
+use shuttle_runtime::SecretStore;
+use tokio::runtime::Runtime;
+use tonic::{transport::server::TcpIncoming, Request, Response, Status};
+use warp::{http::Method, hyper::body::Bytes, path::FullPath, Filter as _, Reply};
+
+use super::{qdrant_path, qdrant_post, qdrant_put};
+use crate::constants::SECRETS;
+
+const KB: &str = "6f1c1b8e-2f4a-5b7c-9d3e-0a1b2c3d4e5f";
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Num(u64),
+    Uuid(String),
+}
+
+impl Key {
+    fn from_json(v: &Value) -> Key {
+        match v {
+            Value::Number(n) => Key::Num(n.as_u64().expect("numeric point id")),
+            Value::String(s) => Key::Uuid(s.clone()),
+            _ => panic!("bad point id {}", v),
+        }
+    }
+
+    fn from_grpc(id: &q::PointId) -> Key {
+        match &id.point_id_options {
+            Some(PointIdOptions::Num(n)) => Key::Num(*n),
+            Some(PointIdOptions::Uuid(u)) => Key::Uuid(u.clone()),
+            None => panic!("empty point id"),
+        }
+    }
+
+    fn json(&self) -> Value {
+        match self {
+            Key::Num(n) => json!(n),
+            Key::Uuid(u) => json!(u),
+        }
+    }
+
+    fn grpc(&self) -> q::PointId {
+        match self {
+            Key::Num(n) => (*n).into(),
+            Key::Uuid(u) => u.clone().into(),
+        }
+    }
+}
+
+type Point = (Vec<f32>, Map<String, Value>);
+type Hit = (Key, f32, Map<String, Value>);
+/// A scroll page and the key the next one starts at.
+type Page = (Vec<(Key, Map<String, Value>)>, Option<Key>);
+
+/// Which payload fields a request asked for.
+enum Fields {
+    Skip,
+    All,
+    Only(Vec<String>),
+}
+
+impl Fields {
+    fn from_json(v: Option<&Value>, default: bool) -> Fields {
+        match v {
+            None => Fields::from_bool(default),
+            Some(Value::Bool(b)) => Fields::from_bool(*b),
+            Some(Value::Array(fields)) => Fields::Only(
+                fields
+                    .iter()
+                    .map(|f| f.as_str().unwrap().to_string())
+                    .collect(),
+            ),
+            Some(v) => panic!("bad with_payload {}", v),
+        }
+    }
+
+    fn from_grpc(s: Option<q::WithPayloadSelector>) -> Fields {
+        match s.and_then(|s| s.selector_options) {
+            Some(SelectorOptions::Enable(b)) => Fields::from_bool(b),
+            Some(SelectorOptions::Include(i)) => Fields::Only(i.fields),
+            _ => Fields::Skip,
+        }
+    }
+
+    fn from_bool(b: bool) -> Fields {
+        if b {
+            Fields::All
+        } else {
+            Fields::Skip
+        }
+    }
+
+    fn pick(&self, p: &Map<String, Value>) -> Option<Map<String, Value>> {
+        match self {
+            Fields::Skip => None,
+            Fields::All => Some(p.clone()),
+            Fields::Only(f) => Some(
+                p.iter()
+                    .filter(|(k, _)| f.contains(k))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+enum Target {
+    Ids(Vec<Key>),
+    Filter(Value),
+}
+
+/// One collection, kept as JSON so both APIs share a single filter evaluator.
+#[derive(Default)]
+struct Store {
+    points: BTreeMap<Key, Point>,
+    operations: u64,
+}
+
+impl Store {
+    fn fixture() -> Store {
+        let mut s = Store::default();
+        s.upsert(vec![
+            (
+                Key::Num(1),
+                vec![1.0, 0.0, 0.0],
+                object(json!({"m": "hello", "u": 1, "c": "m", "i": "chat-a", "d": 1700000000, "g": {"country": "NL"}, "flag": true})),
+            ),
+            (
+                Key::Num(2),
+                vec![0.8, 0.6, 0.0],
+                object(json!({"m": "hi, how can I help?", "u": 0, "c": "m", "i": "chat-a", "d": 1700000060, "x": null})),
+            ),
+            (
+                Key::Uuid(KB.to_string()),
+                vec![0.0, 1.0, 0.0],
+                object(json!({"m": "We open at nine.", "c": "kb", "k": "doc-1", "tags": []})),
+            ),
+            (
+                Key::Num(3),
+                vec![0.0, 0.0, 1.0],
+                object(json!({"m": "site chat", "u": 1, "c": "scm", "i": "chat-b", "d": "2023-11-14T22:13:20Z", "tags": ["a", "b"]})),
+            ),
+        ]);
+        s.operations = 0;
+        s
+    }
+
+    fn filtered<'a>(
+        &'a self,
+        filter: Option<&'a Value>,
+    ) -> impl Iterator<Item = (&'a Key, &'a Point)> {
+        self.points
+            .iter()
+            .filter(move |(k, (_, p))| filter.is_none_or(|f| matches(f, k, p)))
+    }
+
+    fn updated(&mut self) -> u64 {
+        self.operations += 1;
+        self.operations
+    }
+
+    fn upsert(&mut self, points: Vec<(Key, Vec<f32>, Map<String, Value>)>) -> u64 {
+        for (k, v, p) in points {
+            self.points.insert(k, (v, p));
+        }
+        self.updated()
+    }
+
+    fn retrieve(&self, ids: &[Key]) -> Vec<(Key, &Point)> {
+        ids.iter()
+            .filter_map(|k| Some((k.clone(), self.points.get(k)?)))
+            .collect()
+    }
+
+    /// Every match, best first; callers apply offset and limit.
+    fn search(&self, vector: &[f32], filter: Option<&Value>, threshold: Option<f32>) -> Vec<Hit> {
+        let mut hits: Vec<Hit> = self
+            .filtered(filter)
+            .map(|(k, (v, p))| {
+                let score = v.iter().zip(vector).map(|(a, b)| a * b).sum();
+                (k.clone(), score, p.clone())
+            })
+            .filter(|(_, score, _)| threshold.is_none_or(|t| *score >= t))
+            .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits
+    }
+
+    fn scroll(&self, filter: Option<&Value>, offset: Option<&Key>, limit: usize) -> Page {
+        let mut page: Vec<_> = self
+            .filtered(filter)
+            .filter(|(k, _)| offset.is_none_or(|o| *k >= o))
+            .map(|(k, (_, p))| (k.clone(), p.clone()))
+            .take(limit + 1)
+            .collect();
+        let next = (page.len() > limit).then(|| page.pop().unwrap().0);
+        (page, next)
+    }
+
+    fn targets(&self, target: &Target) -> Vec<Key> {
+        match target {
+            Target::Ids(ids) => ids.clone(),
+            Target::Filter(f) => self.filtered(Some(f)).map(|(k, _)| k.clone()).collect(),
+        }
+    }
+
+    fn set_payload(&mut self, target: &Target, payload: Map<String, Value>) -> u64 {
+        for k in self.targets(target) {
+            if let Some((_, p)) = self.points.get_mut(&k) {
+                p.extend(payload.clone());
+            }
+        }
+        self.updated()
+    }
+
+    fn delete(&mut self, target: &Target) -> u64 {
+        for k in self.targets(target) {
+            self.points.remove(&k);
+        }
+        self.updated()
+    }
+
+    fn dump(&self) -> Value {
+        self.points
+            .iter()
+            .map(|(k, (v, p))| json!({"id": k.json(), "vector": v, "payload": p}))
+            .collect()
+    }
+}
+
+/// Groups hits, best first, by their first value of `group_by`.
+fn groups(hits: Vec<Hit>, group_by: &str, limit: usize, size: usize) -> Vec<(Value, Vec<Hit>)> {
+    let mut groups: Vec<(Value, Vec<Hit>)> = vec![];
+    for hit in hits {
+        let Some(id) = lookup(&hit.2, group_by).first().map(|v| (*v).clone()) else {
+            continue;
+        };
+        match groups.iter_mut().find(|(g, _)| *g == id) {
+            Some((_, hits)) => {
+                if hits.len() < size {
+                    hits.push(hit)
+                }
+            }
+            None => {
+                if groups.len() < limit {
+                    groups.push((id, vec![hit]))
+                }
+            }
+        }
+    }
+    groups
+}
+
+fn object(v: Value) -> Map<String, Value> {
+    match v {
+        Value::Object(o) => o,
+        v => panic!("expected an object, got {}", v),
+    }
+}
+
+fn field<'a>(p: &'a Map<String, Value>, key: &str) -> Option<&'a Value> {
+    let mut parts = key.split('.');
+    let mut v = p.get(parts.next()?)?;
+    for part in parts {
+        v = v.get(part)?;
+    }
+    Some(v)
+}
+
+/// The values of `key` as filters see them: arrays flattened, nulls dropped.
+fn lookup<'a>(p: &'a Map<String, Value>, key: &str) -> Vec<&'a Value> {
+    match field(p, key) {
+        None | Some(Value::Null) => vec![],
+        Some(Value::Array(a)) => a.iter().filter(|v| !v.is_null()).collect(),
+        Some(v) => vec![v],
+    }
+}
+
+fn matches(f: &Value, id: &Key, p: &Map<String, Value>) -> bool {
+    let clause = |k: &str| match f.get(k) {
+        Some(Value::Array(cs)) => cs.iter().collect(),
+        Some(c @ Value::Object(_)) => vec![c],
+        _ => vec![],
+    };
+    let should = clause("should");
+    clause("must").iter().all(|c| holds(c, id, p))
+        && (should.is_empty() || should.iter().any(|c| holds(c, id, p)))
+        && !clause("must_not").iter().any(|c| holds(c, id, p))
+}
+
+fn holds(c: &Value, id: &Key, p: &Map<String, Value>) -> bool {
+    if let Some(key) = c.get("key").and_then(Value::as_str) {
+        let values = lookup(p, key);
+        return match (c.get("match"), c.get("range")) {
+            (Some(m), _) => match m.get("any").and_then(Value::as_array) {
+                Some(any) => values.iter().any(|v| any.contains(v)),
+                None => values.contains(&&m["value"]),
+            },
+            (None, Some(r)) => values.iter().any(|v| in_range(v, r)),
+            _ => panic!("unsupported condition {}", c),
+        };
+    }
+    if let Some(e) = c.get("is_empty") {
+        return lookup(p, e["key"].as_str().unwrap()).is_empty();
+    }
+    if let Some(e) = c.get("is_null") {
+        return field(p, e["key"].as_str().unwrap()) == Some(&Value::Null);
+    }
+    if let Some(ids) = c.get("has_id") {
+        return ids
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|i| Key::from_json(i) == *id);
+    }
+    matches(c, id, p)
+}
+
+/// Numbers compare as numbers; string bounds are RFC 3339 datetimes, which
+/// compare as strings when they're all in UTC, as the fixture's are.
+fn in_range(v: &Value, r: &Value) -> bool {
+    let bound = |b: &str, ok: fn(Ordering) -> bool| match &r[b] {
+        Value::Null => true,
+        Value::String(s) => v.as_str().is_some_and(|v| ok(v.cmp(s))),
+        b => match (v.as_f64(), b.as_f64()) {
+            (Some(v), Some(b)) => v.partial_cmp(&b).is_some_and(ok),
+            _ => false,
+        },
+    };
+    bound("gt", Ordering::is_gt)
+        && bound("gte", Ordering::is_ge)
+        && bound("lt", Ordering::is_lt)
+        && bound("lte", Ordering::is_le)
+}
+
+fn filter_json(f: &q::Filter) -> Value {
+    let all = |cs: &[q::Condition]| cs.iter().map(condition_json).collect::<Vec<_>>();
+    json!({"must": all(&f.must), "should": all(&f.should), "must_not": all(&f.must_not)})
+}
+
+fn condition_json(c: &q::Condition) -> Value {
+    match &c.condition_one_of {
+        Some(ConditionOneOf::Field(f)) => match (&f.r#match, &f.range) {
+            (Some(m), None) => json!({"key": f.key, "match": match &m.match_value {
+                Some(MatchValue::Keyword(s)) => json!({"value": s}),
+                Some(MatchValue::Integer(i)) => json!({"value": i}),
+                Some(MatchValue::Boolean(b)) => json!({"value": b}),
+                Some(MatchValue::Keywords(k)) => json!({"any": k.strings}),
+                Some(MatchValue::Integers(i)) => json!({"any": i.integers}),
+                m => panic!("unsupported match {:?}", m),
+            }}),
+            (None, Some(r)) => {
+                json!({"key": f.key, "range": {"gt": r.gt, "gte": r.gte, "lt": r.lt, "lte": r.lte}})
+            }
+            _ => panic!("unsupported field condition {:?}", f),
+        },
+        Some(ConditionOneOf::IsEmpty(e)) => json!({"is_empty": {"key": e.key}}),
+        Some(ConditionOneOf::IsNull(e)) => json!({"is_null": {"key": e.key}}),
+        Some(ConditionOneOf::HasId(h)) => {
+            json!({"has_id": h.has_id.iter().map(|i| Key::from_grpc(i).json()).collect::<Vec<_>>()})
+        }
+        Some(ConditionOneOf::Filter(f)) => filter_json(f),
+        c => panic!("unsupported condition {:?}", c),
+    }
+}
+
+fn payload_json(p: HashMap<String, q::Value>) -> Map<String, Value> {
+    Payload::from(p).into()
+}
+
+fn payload_grpc(p: Option<Map<String, Value>>) -> HashMap<String, q::Value> {
+    p.map(|p| Payload::from(p).into()).unwrap_or_default()
+}
+
+#[allow(deprecated)]
+fn dense(v: Option<q::Vectors>) -> Vec<f32> {
+    match v.and_then(|v| v.vectors_options) {
+        Some(VectorsOptions::Vector(v)) => match v.vector {
+            Some(vector::Vector::Dense(d)) => d.data,
+            _ => v.data,
+        },
+        v => panic!("expected a dense vector, got {:?}", v),
+    }
+}
+
+fn target_grpc(s: Option<q::PointsSelector>) -> Target {
+    match s.and_then(|s| s.points_selector_one_of) {
+        Some(PointsSelectorOneOf::Points(ids)) => {
+            Target::Ids(ids.ids.iter().map(Key::from_grpc).collect())
+        }
+        Some(PointsSelectorOneOf::Filter(f)) => Target::Filter(filter_json(&f)),
+        None => panic!("no points selector"),
+    }
+}
+
+fn target_json(body: &Value) -> Target {
+    match (body.get("points"), body.get("filter")) {
+        (Some(ids), None) => {
+            Target::Ids(ids.as_array().unwrap().iter().map(Key::from_json).collect())
+        }
+        (None, Some(f)) => Target::Filter(f.clone()),
+        _ => panic!("bad points selector {}", body),
+    }
+}
+
+#[derive(Clone, Default)]
+struct Mock {
+    store: Arc<Mutex<Store>>,
+    rest: Arc<AtomicUsize>,
+    grpc: Arc<AtomicUsize>,
+}
+
+impl Mock {
+    fn store(&self) -> std::sync::MutexGuard<'_, Store> {
+        self.store.lock().unwrap()
+    }
+
+    fn rest(&self, method: Method, path: FullPath, body: Bytes) -> warp::reply::Response {
+        self.rest.fetch_add(1, SeqCst);
+        let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        let filter = body.get("filter").filter(|f| !f.is_null());
+        let op = path
+            .as_str()
+            .strip_prefix("/collections/t/")
+            .unwrap_or_default();
+        let mut store = self.store();
+        let update = |id: u64| json!({"operation_id": id, "status": "completed"});
+        let scored = |(k, score, p): Hit, fields: &Fields| json!({"id": k.json(), "version": 0, "score": score, "payload": fields.pick(&p), "vector": null});
+        let result = match (method.as_str(), op) {
+            ("PUT", "points") => update(
+                store.upsert(
+                    body["points"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|p| {
+                            (
+                                Key::from_json(&p["id"]),
+                                serde_json::from_value(p["vector"].clone()).unwrap(),
+                                p.get("payload").cloned().map(object).unwrap_or_default(),
+                            )
+                        })
+                        .collect(),
+                ),
+            ),
+            ("POST", "points") => {
+                let ids: Vec<Key> = body["ids"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(Key::from_json)
+                    .collect();
+                let fields = Fields::from_json(body.get("with_payload"), true);
+                let with_vector = body.get("with_vector") == Some(&Value::Bool(true));
+                store
+                    .retrieve(&ids)
+                    .into_iter()
+                    .map(|(k, (v, p))| {
+                        json!({"id": k.json(), "payload": fields.pick(p), "vector": if with_vector { json!(v) } else { Value::Null }})
+                    })
+                    .collect()
+            }
+            ("POST", "points/search") => {
+                let fields = Fields::from_json(body.get("with_payload"), false);
+                let vector: Vec<f32> = serde_json::from_value(body["vector"].clone()).unwrap();
+                let threshold = body["score_threshold"].as_f64().map(|t| t as f32);
+                store
+                    .search(&vector, filter, threshold)
+                    .into_iter()
+                    .skip(body["offset"].as_u64().unwrap_or(0) as usize)
+                    .take(body["limit"].as_u64().unwrap() as usize)
+                    .map(|h| scored(h, &fields))
+                    .collect()
+            }
+            ("POST", "points/search/groups") => {
+                let fields = Fields::from_json(body.get("with_payload"), false);
+                let vector: Vec<f32> = serde_json::from_value(body["vector"].clone()).unwrap();
+                let groups = groups(
+                    store.search(&vector, filter, None),
+                    body["group_by"].as_str().unwrap(),
+                    body["limit"].as_u64().unwrap() as usize,
+                    body["group_size"].as_u64().unwrap() as usize,
+                );
+                json!({"groups": groups.into_iter().map(|(id, hits)| json!({
+                    "id": id,
+                    "hits": hits.into_iter().map(|h| scored(h, &fields)).collect::<Vec<_>>(),
+                })).collect::<Vec<_>>()})
+            }
+            ("POST", "points/scroll") => {
+                let fields = Fields::from_json(body.get("with_payload"), true);
+                let limit = body["limit"].as_u64().unwrap_or(10) as usize;
+                let (page, next) = match body.get("order_by").and_then(Value::as_str) {
+                    Some(key) => {
+                        let mut page: Vec<_> = store
+                            .filtered(filter)
+                            .filter_map(|(k, (_, p))| {
+                                Some((field(p, key)?.as_f64()?, k.clone(), p.clone()))
+                            })
+                            .collect();
+                        page.sort_by(|a, b| a.0.total_cmp(&b.0));
+                        (
+                            page.into_iter()
+                                .take(limit)
+                                .map(|(_, k, p)| (k, p))
+                                .collect(),
+                            None,
+                        )
+                    }
+                    None => {
+                        let offset = body
+                            .get("offset")
+                            .filter(|o| !o.is_null())
+                            .map(Key::from_json);
+                        store.scroll(filter, offset.as_ref(), limit)
+                    }
+                };
+                json!({
+                    "points": page.into_iter().map(|(k, p)| json!({"id": k.json(), "payload": fields.pick(&p), "vector": null})).collect::<Vec<_>>(),
+                    "next_page_offset": next.map(|k| k.json()),
+                })
+            }
+            ("POST", "points/payload") => {
+                update(store.set_payload(&target_json(&body), object(body["payload"].clone())))
+            }
+            ("POST", "points/delete") => update(store.delete(&target_json(&body))),
+            ("POST", "points/count") => json!({"count": store.filtered(filter).count()}),
+            _ => return warp::http::StatusCode::NOT_FOUND.into_response(),
+        };
+        warp::reply::json(&json!({"result": result, "status": "ok", "time": 0.001})).into_response()
+    }
+
+    fn served(&self) -> (usize, usize) {
+        (self.rest.load(SeqCst), self.grpc.load(SeqCst))
+    }
+}
+
+fn updated(id: u64) -> Response<q::PointsOperationResponse> {
+    Response::new(q::PointsOperationResponse {
+        result: Some(q::UpdateResult {
+            operation_id: Some(id),
+            status: q::UpdateStatus::Completed as i32,
+        }),
+        time: 0.001,
+        ..Default::default()
+    })
+}
+
+fn scored_grpc((k, score, p): Hit, fields: &Fields) -> q::ScoredPoint {
+    q::ScoredPoint {
+        id: Some(k.grpc()),
+        payload: payload_grpc(fields.pick(&p)),
+        score,
+        ..Default::default()
+    }
+}
+
+fn retrieved_grpc(k: Key, p: &Map<String, Value>, fields: &Fields) -> q::RetrievedPoint {
+    q::RetrievedPoint {
+        id: Some(k.grpc()),
+        payload: payload_grpc(fields.pick(p)),
+        ..Default::default()
+    }
+}
+
+/// Fills in the generated signature for calls the transport never makes.
+macro_rules! unimplemented_calls {
+    ($($name:ident($req:ident) -> $res:ident;)*) => {$(
+        fn $name<'life0, 'async_trait>(
+            &'life0 self,
+            _: Request<q::$req>,
+        ) -> Pin<Box<dyn Future<Output = Result<Response<q::$res>, Status>> + Send + 'async_trait>>
+        where
+            'life0: 'async_trait,
+            Self: 'async_trait,
+        {
+            Box::pin(async { Err(Status::unimplemented(stringify!($name))) })
+        }
+    )*};
+}
+
+#[tonic::async_trait]
+impl Points for Mock {
+    async fn upsert(
+        &self,
+        r: Request<q::UpsertPoints>,
+    ) -> Result<Response<q::PointsOperationResponse>, Status> {
+        self.grpc.fetch_add(1, SeqCst);
+        let points = r
+            .into_inner()
+            .points
+            .into_iter()
+            .map(|p| {
+                (
+                    Key::from_grpc(&p.id.unwrap()),
+                    dense(p.vectors),
+                    payload_json(p.payload),
+                )
+            })
+            .collect();
+        Ok(updated(self.store().upsert(points)))
+    }
+
+    async fn get(&self, r: Request<q::GetPoints>) -> Result<Response<q::GetResponse>, Status> {
+        self.grpc.fetch_add(1, SeqCst);
+        let r = r.into_inner();
+        let ids: Vec<Key> = r.ids.iter().map(Key::from_grpc).collect();
+        let fields = Fields::from_grpc(r.with_payload);
+        Ok(Response::new(q::GetResponse {
+            result: self
+                .store()
+                .retrieve(&ids)
+                .into_iter()
+                .map(|(k, (_, p))| retrieved_grpc(k, p, &fields))
+                .collect(),
+            time: 0.001,
+            ..Default::default()
+        }))
+    }
+
+    async fn search(
+        &self,
+        r: Request<q::SearchPoints>,
+    ) -> Result<Response<q::SearchResponse>, Status> {
+        self.grpc.fetch_add(1, SeqCst);
+        let r = r.into_inner();
+        let filter = r.filter.as_ref().map(filter_json);
+        let fields = Fields::from_grpc(r.with_payload);
+        Ok(Response::new(q::SearchResponse {
+            result: self
+                .store()
+                .search(&r.vector, filter.as_ref(), r.score_threshold)
+                .into_iter()
+                .skip(r.offset.unwrap_or(0) as usize)
+                .take(r.limit as usize)
+                .map(|h| scored_grpc(h, &fields))
+                .collect(),
+            time: 0.001,
+            ..Default::default()
+        }))
+    }
+
+    async fn search_groups(
+        &self,
+        r: Request<q::SearchPointGroups>,
+    ) -> Result<Response<q::SearchGroupsResponse>, Status> {
+        self.grpc.fetch_add(1, SeqCst);
+        let r = r.into_inner();
+        let filter = r.filter.as_ref().map(filter_json);
+        let fields = Fields::from_grpc(r.with_payload);
+        let hits = self.store().search(&r.vector, filter.as_ref(), None);
+        let groups = groups(hits, &r.group_by, r.limit as usize, r.group_size as usize)
+            .into_iter()
+            .map(|(id, hits)| q::PointGroup {
+                id: Some(q::GroupId {
+                    kind: Some(match id {
+                        Value::String(s) => group_id::Kind::StringValue(s),
+                        Value::Number(n) => match n.as_u64() {
+                            Some(u) => group_id::Kind::UnsignedValue(u),
+                            None => group_id::Kind::IntegerValue(n.as_i64().unwrap()),
+                        },
+                        id => panic!("bad group id {}", id),
+                    }),
+                }),
+                hits: hits.into_iter().map(|h| scored_grpc(h, &fields)).collect(),
+                lookup: None,
+            })
+            .collect();
+        Ok(Response::new(q::SearchGroupsResponse {
+            result: Some(q::GroupsResult { groups }),
+            time: 0.001,
+            ..Default::default()
+        }))
+    }
+
+    async fn scroll(
+        &self,
+        r: Request<q::ScrollPoints>,
+    ) -> Result<Response<q::ScrollResponse>, Status> {
+        self.grpc.fetch_add(1, SeqCst);
+        let r = r.into_inner();
+        let filter = r.filter.as_ref().map(filter_json);
+        let fields = Fields::from_grpc(r.with_payload);
+        let offset = r.offset.as_ref().map(Key::from_grpc);
+        let (page, next) = self.store().scroll(
+            filter.as_ref(),
+            offset.as_ref(),
+            r.limit.unwrap_or(10) as usize,
+        );
+        Ok(Response::new(q::ScrollResponse {
+            next_page_offset: next.map(|k| k.grpc()),
+            result: page
+                .into_iter()
+                .map(|(k, p)| retrieved_grpc(k, &p, &fields))
+                .collect(),
+            time: 0.001,
+            ..Default::default()
+        }))
+    }
+
+    async fn set_payload(
+        &self,
+        r: Request<q::SetPayloadPoints>,
+    ) -> Result<Response<q::PointsOperationResponse>, Status> {
+        self.grpc.fetch_add(1, SeqCst);
+        let r = r.into_inner();
+        let target = target_grpc(r.points_selector);
+        Ok(updated(
+            self.store().set_payload(&target, payload_json(r.payload)),
+        ))
+    }
+
+    async fn delete(
+        &self,
+        r: Request<q::DeletePoints>,
+    ) -> Result<Response<q::PointsOperationResponse>, Status> {
+        self.grpc.fetch_add(1, SeqCst);
+        let target = target_grpc(r.into_inner().points);
+        Ok(updated(self.store().delete(&target)))
+    }
+
+    async fn count(
+        &self,
+        r: Request<q::CountPoints>,
+    ) -> Result<Response<q::CountResponse>, Status> {
+        self.grpc.fetch_add(1, SeqCst);
+        let filter = r.into_inner().filter.as_ref().map(filter_json);
+        Ok(Response::new(q::CountResponse {
+            result: Some(q::CountResult {
+                count: self.store().filtered(filter.as_ref()).count() as u64,
+            }),
+            time: 0.001,
+            ..Default::default()
+        }))
+    }
+
+    unimplemented_calls! {
+        update_vectors(UpdatePointVectors) -> PointsOperationResponse;
+        delete_vectors(DeletePointVectors) -> PointsOperationResponse;
+        overwrite_payload(SetPayloadPoints) -> PointsOperationResponse;
+        delete_payload(DeletePayloadPoints) -> PointsOperationResponse;
+        clear_payload(ClearPayloadPoints) -> PointsOperationResponse;
+        create_field_index(CreateFieldIndexCollection) -> PointsOperationResponse;
+        delete_field_index(DeleteFieldIndexCollection) -> PointsOperationResponse;
+        create_vector_name(CreateVectorNameRequest) -> PointsOperationResponse;
+        delete_vector_name(DeleteVectorNameRequest) -> PointsOperationResponse;
+        search_batch(SearchBatchPoints) -> SearchBatchResponse;
+        recommend(RecommendPoints) -> RecommendResponse;
+        recommend_batch(RecommendBatchPoints) -> RecommendBatchResponse;
+        recommend_groups(RecommendPointGroups) -> RecommendGroupsResponse;
+        discover(DiscoverPoints) -> DiscoverResponse;
+        discover_batch(DiscoverBatchPoints) -> DiscoverBatchResponse;
+        update_batch(UpdateBatchPoints) -> UpdateBatchResponse;
+        query(QueryPoints) -> QueryResponse;
+        query_batch(QueryBatchPoints) -> QueryBatchResponse;
+        query_groups(QueryPointGroups) -> QueryGroupsResponse;
+        facet(FacetCounts) -> FacetResponse;
+        search_matrix_pairs(SearchMatrixPoints) -> SearchMatrixPairsResponse;
+        search_matrix_offsets(SearchMatrixPoints) -> SearchMatrixOffsetsResponse;
+    }
+}
+
+/// Starts the mock's REST and gRPC servers, returning their URLs.
+fn serve(mock: &Mock) -> (String, String) {
+    let m = mock.clone();
+    let routes = warp::any()
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::body::bytes())
+        .map(move |method, path, body| m.rest(method, path, body));
+    let (rest, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    let incoming = TcpIncoming::bind(([127, 0, 0, 1], 0).into()).unwrap();
+    let grpc = incoming.local_addr().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(PointsServer::new(mock.clone()))
+            .serve_with_incoming(incoming),
+    );
+    (format!("http://{}", rest), format!("http://{}", grpc))
+}
+
+fn secrets(rest: &str, grpc: &str, use_grpc: bool) -> SecretStore {
+    let mut s = json!({"QDRANT_URL": rest, "QDRANT_KEY": "key", "QDRANT_GRPC_URL": grpc});
+    if use_grpc {
+        s["QDRANT_TRANSPORT"] = json!("grpc");
+    }
+    serde_json::from_value(s).unwrap()
+}
+
+/// Drops what legitimately differs between transports: timings, empty
+/// payloads, which gRPC can't tell apart from absent ones, and the last digits
+/// of scores, which are `f32`s that REST round-trips through decimal.
+fn normalize(mut v: Value) -> Value {
+    if let Some(o) = v.as_object_mut() {
+        o.remove("time");
+    }
+    fn noise(v: &mut Value) {
+        match v {
+            Value::Object(o) => {
+                if o.get("payload") == Some(&json!({})) {
+                    o.insert("payload".to_string(), Value::Null);
+                }
+                if let Some(s) = o.get("score").and_then(Value::as_f64) {
+                    o.insert("score".to_string(), json!(s as f32));
+                }
+                o.values_mut().for_each(noise);
+            }
+            Value::Array(a) => a.iter_mut().for_each(noise),
+            _ => {}
+        }
+    }
+    noise(&mut v);
+    v
+}
+
+/// Method, operation, body, and whether gRPC should serve it when enabled.
+type Case = (&'static str, Value);
+
+/// One runtime for every test: the gRPC client is built once per process and
+/// stops working when the runtime it was built on is dropped.
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .unwrap()
+});
+
+/// The mock, with the REST and gRPC addresses it listens on.
+static MOCK: Lazy<(Mock, String, String)> = Lazy::new(|| {
+    let mock = Mock::default();
+    let (rest, grpc) = RUNTIME.block_on(async { serve(&mock) });
+    (mock, rest, grpc)
+});
+
+/// Tests share the mock and the secrets, so they take turns.
+static TURN: Mutex<()> = Mutex::new(());
+
+/// Sends each request over REST and then over gRPC, against a fresh fixture
+/// each time, and checks both the answers and the store they leave behind
+/// agree. `by_grpc` says whether the gRPC run should be served by gRPC or fall
+/// back to REST.
+fn agree(method: &str, cases: Vec<Case>, by_grpc: bool) {
+    let _turn = TURN.lock().unwrap_or_else(|e| e.into_inner());
+    let (mock, rest, grpc) = &*MOCK;
+    RUNTIME.block_on(async {
+        for (op, body) in cases {
+            let mut seen = vec![];
+            for use_grpc in [false, true] {
+                *mock.store() = Store::fixture();
+                *SECRETS.lock().await = secrets(rest, grpc, use_grpc);
+                let before = mock.served();
+                let path = qdrant_path(&format!("collections/t/{}", op)).await.unwrap();
+                let res = match method {
+                    "PUT" => qdrant_put(&path, &body).await,
+                    _ => qdrant_post(&path, &body).await,
+                }
+                .unwrap_or_else(|e| panic!("{} {} {}: {}", method, op, body, e));
+                let after = mock.served();
+                assert_eq!(
+                    (after.0 - before.0, after.1 - before.1),
+                    if use_grpc && by_grpc { (0, 1) } else { (1, 0) },
+                    "{} {} {} served by the wrong transport",
+                    method,
+                    op,
+                    body
+                );
+                seen.push((normalize(res), mock.store().dump()));
+            }
+            assert_eq!(seen[0], seen[1], "{} {} {}", method, op, body);
+        }
+    });
+}
+
+/// Points fetched by ID.
+#[test]
+fn retrieve() {
+    agree(
+        "POST",
+        vec![
+            ("points", json!({"ids": [1, KB], "with_payload": true})),
+            (
+                "points",
+                json!({"ids": [2, 1, 9], "with_payload": ["m", "u"]}),
+            ),
+            ("points", json!({"ids": [3]})),
+            (
+                "points",
+                json!({"ids": [1], "with_payload": false, "with_vector": false}),
+            ),
+        ],
+        true,
+    );
+}
+
+/// Paged and filtered scrolls, covering each kind of condition.
+#[test]
+fn scroll() {
+    let chat_a = json!({"key": "i", "match": {"value": "chat-a"}});
+    agree(
+        "POST",
+        vec![
+            (
+                "points/scroll",
+                json!({"filter": {"must": [chat_a]}, "limit": 10}),
+            ),
+            ("points/scroll", json!({"limit": 2})),
+            ("points/scroll", json!({"limit": 2, "offset": 3})),
+            (
+                "points/scroll",
+                json!({"limit": 2, "offset": KB, "filter": null}),
+            ),
+            (
+                "points/scroll",
+                json!({"filter": {"should": [
+                    {"key": "c", "match": {"value": "scm"}},
+                    {"must": [{"key": "u", "match": {"value": 1}}, {"key": "flag", "match": {"value": true}}]},
+                ]}}),
+            ),
+            (
+                "points/scroll",
+                json!({"filter": {"must": [{"has_id": [1, 3]}], "must_not": [{"key": "c", "match": {"value": "scm"}}]}}),
+            ),
+            (
+                "points/scroll",
+                json!({"filter": {"must": {"is_empty": {"key": "tags"}}}, "with_payload": false}),
+            ),
+            (
+                "points/scroll",
+                json!({"filter": {"must": [{"is_null": {"key": "x"}}]}}),
+            ),
+            (
+                "points/scroll",
+                json!({"filter": {"must": [{"key": "d", "range": {"gte": 1600000000, "lt": 1700000060}}]}}),
+            ),
+            (
+                "points/scroll",
+                json!({"filter": {"must": [{"key": "g.country", "match": {"value": "NL"}}]}, "with_payload": ["m"]}),
+            ),
+            (
+                "points/scroll",
+                json!({"filter": {"must": [{"key": "c", "match": {"any": ["m", "kb"]}}, {"key": "tags", "match": {"any": ["a"]}}]}}),
+            ),
+            (
+                "points/scroll",
+                json!({"filter": {"must_not": [{"key": "u", "match": {"any": [0, 1]}}]}}),
+            ),
+        ],
+        true,
+    );
+}
+
+/// Vector searches, with and without a filter and threshold.
+#[test]
+fn search() {
+    agree(
+        "POST",
+        vec![
+            (
+                "points/search",
+                json!({"vector": [1.0, 0.0, 0.0], "limit": 3, "with_payload": true}),
+            ),
+            (
+                "points/search",
+                json!({
+                    "vector": [1.0, 0.2, 0.0],
+                    "limit": 2,
+                    "offset": 1,
+                    "score_threshold": 0.1,
+                    "filter": {"must_not": [{"key": "c", "match": {"value": "kb"}}]},
+                    "with_payload": ["m", "i"],
+                }),
+            ),
+        ],
+        true,
+    );
+}
+
+/// Searches grouped on a payload key.
+#[test]
+fn search_groups() {
+    agree(
+        "POST",
+        vec![(
+            "points/search/groups",
+            json!({"vector": [1.0, 0.5, 0.5], "group_by": "i", "limit": 2, "group_size": 1, "with_payload": true}),
+        )],
+        true,
+    );
+}
+
+/// Counts, exact and over a filter.
+#[test]
+fn count() {
+    agree(
+        "POST",
+        vec![
+            (
+                "points/count",
+                json!({"filter": {"must": [{"key": "c", "match": {"value": "m"}}]}, "exact": true}),
+            ),
+            ("points/count", json!({})),
+        ],
+        true,
+    );
+}
+
+/// Upserts of new and existing points.
+#[test]
+fn upsert() {
+    agree(
+        "PUT",
+        vec![(
+            "points?wait=true",
+            json!({"points": [
+                {"id": 4, "vector": [0.5, 0.5, 0.0], "payload": {"m": "new", "c": "m", "n": {"a": [1, 2.5, null]}}},
+                {"id": 1, "vector": [1.0, 0.0, 0.0], "payload": {"m": "replaced"}},
+                {"id": KB, "vector": [0.0, 1.0, 0.0]},
+            ]}),
+        )],
+        true,
+    );
+}
+
+/// Payload set on listed points and on a filter.
+#[test]
+fn set_payload() {
+    let chat_a = json!({"key": "i", "match": {"value": "chat-a"}});
+    agree(
+        "POST",
+        vec![
+            (
+                "points/payload?wait=true",
+                json!({"payload": {"z": true}, "points": [1, 2]}),
+            ),
+            (
+                "points/payload",
+                json!({"payload": {"o": "t1"}, "filter": {"must": [chat_a]}}),
+            ),
+        ],
+        true,
+    );
+}
+
+/// Deletes by ID and by filter.
+#[test]
+fn delete() {
+    agree(
+        "POST",
+        vec![
+            ("points/delete?wait=true", json!({"points": [2, KB]})),
+            (
+                "points/delete",
+                json!({"filter": {"must": [{"key": "c", "match": {"value": "scm"}}]}}),
+            ),
+        ],
+        true,
+    );
+}
+
+/// Requests gRPC can't express, which must go over REST.
+#[test]
+fn untranslatable_requests_use_rest() {
+    agree(
+        "POST",
+        vec![
+            (
+                "points/scroll",
+                json!({"filter": {"must": [{"key": "d", "range": {"gte": "2023-01-01T00:00:00Z"}}]}}),
+            ),
+            (
+                "points/count",
+                json!({"filter": {"must": [{"key": "d", "range": {"gt": 1600000000, "lte": "2024-01-01T00:00:00Z"}}]}}),
+            ),
+            ("points/scroll", json!({"limit": 3, "order_by": "d"})),
+            ("points", json!({"ids": [1], "with_vector": true})),
+            (
+                "points/search",
+                json!({"vector": [1.0, 0.0, 0.0], "limit": 1, "with_vector": true}),
+            ),
+            (
+                "points/search",
+                json!({"vector": [1.0, 0.0, 0.0], "limit": 2, "offset": "1"}),
+            ),
+            (
+                "points/search",
+                json!({"vector": [1.0, 0.0, 0.0], "limit": 2, "score_threshold": "0.5"}),
+            ),
+            ("points/scroll", json!({"limit": "2"})),
+            ("points/count", json!({"exact": "false"})),
+        ],
+        false,
+    );
+}
@@ -0,0 +1,282 @@
+//! Answers visitor questions: recent chat history plus the knowledge-base
+//! chunks and past answers closest to the question go to the LLM, and the
+//! retrieved points come back as numbered citations.
+
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    app::{AppError, AppResult},
+    constants::SECRETS,
+    kb::KB_CATEGORY,
+    llm,
+    search::search,
+    store::{self, save_turn, Turn, MESSAGE_CATEGORY},
+    tenant::Tenant,
+    transcript,
+    util::embedding,
+};
+
+/// Earlier messages of the chat sent along with the question.
+const HISTORY: usize = 10;
+const KB_RESULTS: usize = 5;
+const ANSWER_RESULTS: usize = 3;
+/// Largest question request body, in bytes.
+pub const MAX_QUESTION_BYTES: u64 = 8 * 1024;
+
+const SYSTEM_PROMPT: &str = "You are the assistant of this website. Answer the \
+visitor's question using the numbered sources below when they are relevant, and \
+cite them inline as [n]. If the sources don't cover the question, say so rather \
+than guessing.";
+
+#[derive(Deserialize)]
+pub struct Question {
+    pub text: String,
+    #[serde(default)]
+    pub page: Option<String>,
+    /// Client timestamp of the question; the server's clock when missing.
+    #[serde(default)]
+    pub date: Option<Value>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Citation {
+    pub n: usize,
+    pub id: Value,
+    pub score: f64,
+    pub category: Option<String>,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    /// Knowledge-base document the chunk belongs to.
+    pub document: Option<String>,
+    pub text: String,
+}
+
+impl Citation {
+    fn from_point(n: usize, p: &Value) -> Self {
+        let string = |k: &str| p["payload"][k].as_str().map(str::to_string);
+        Citation {
+            n,
+            id: p["id"].clone(),
+            score: p["score"].as_f64().unwrap_or_default(),
+            category: string("c"),
+            title: string("t"),
+            url: string("s"),
+            document: string("k"),
+            text: string("m").unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Answer {
+    /// ID of the stored question, as `/add` returns it.
+    pub id: String,
+    pub answer: String,
+    pub citations: Vec<Citation>,
+}
+
+/// Retrieves sources for `question` and builds the completion request:
+/// system prompt with the sources, the last `HISTORY` messages of `chat`,
+/// then the question. Earlier answers only come from `chat` itself, so one
+/// visitor's conversation never surfaces in another's, and everything only
+/// from tenant `t`.
+pub async fn prompt(
+    chat: &str,
+    question: &str,
+    t: &Tenant,
+) -> AppResult<(Vec<Value>, Vec<Citation>)> {
+    let history = transcript::load(chat, t).await?;
+    let vector = embedding(question).await?;
+    let mut points = search(
+        vector.clone(),
+        t.scope(Some(
+            json!({"must": [{"key": "c", "match": {"value": KB_CATEGORY}}]}),
+        )),
+        KB_RESULTS,
+        json!(true),
+    )
+    .await?;
+    points.extend(
+        search(
+            vector,
+            t.scope(Some(json!({"must": [
+                {"key": "c", "match": {"value": MESSAGE_CATEGORY}},
+                {"key": "u", "match": {"value": 0}},
+                {"key": "i", "match": {"value": chat}},
+            ]}))),
+            ANSWER_RESULTS,
+            json!(true),
+        )
+        .await?,
+    );
+    let citations: Vec<Citation> = points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| Citation::from_point(i + 1, p))
+        .collect();
+
+    let mut system = SECRETS
+        .lock()
+        .await
+        .get("LLM_SYSTEM_PROMPT")
+        .unwrap_or_else(|| SYSTEM_PROMPT.to_string());
+    system.push_str("\n\nSources:\n");
+    for c in &citations {
+        let heading = c.title.as_deref().unwrap_or(match c.category.as_deref() {
+            Some(MESSAGE_CATEGORY) => "earlier answer",
+            _ => "",
+        });
+        system.push_str(&format!("[{}] {}\n{}\n\n", c.n, heading, c.text));
+    }
+    let mut messages = vec![json!({"role": "system", "content": system})];
+    let skip = history.len().saturating_sub(HISTORY);
+    messages.extend(
+        history
+            .iter()
+            .skip(skip)
+            .map(|m| json!({"role": m.role, "content": m.text})),
+    );
+    messages.push(json!({"role": "user", "content": question}));
+    Ok((messages, citations))
+}
+
+/// Answers `q` in chat `chat` and stores the question and answer like `/add`.
+pub async fn answer(
+    chat: String,
+    q: Question,
+    ip: Option<String>,
+    visitor: Option<String>,
+    tenant: Tenant,
+) -> AppResult<Answer> {
+    let (messages, citations) = prompt(&chat, &q.text, &tenant).await?;
+    let question_date = q.date.unwrap_or_else(store::now);
+    let answer = llm::complete(&messages).await?;
+    let id = save_turn(Turn {
+        chat,
+        page: q.page,
+        ip,
+        visitor,
+        tenant: tenant.0,
+        question: q.text,
+        question_date,
+        answer: answer.clone(),
+        answer_date: store::now(),
+        incomplete: false,
+    })
+    .await?;
+    Ok(Answer {
+        id,
+        answer,
+        citations,
+    })
+}
+
+/// What a streamed answer emits: pieces of the answer, then the stored
+/// question's ID, or an error that ended the answer early.
+pub enum Progress {
+    Token(String),
+    Done(String),
+    Failed,
+}
+
+/// A turn whose answer is still being streamed. If it is dropped before
+/// `finish`, typically because the client went away, what was received so far
+/// is stored as an incomplete turn in the background.
+struct Pending {
+    turn: Option<Turn>,
+    /// ID the turn was stored under, once `finish` has stored it.
+    id: Option<String>,
+}
+
+impl Pending {
+    /// Stores the turn. Later calls store nothing and return the first
+    /// call's ID. The turn is saved from its own task, so dropping the stream
+    /// while it is being saved doesn't lose it.
+    async fn finish(&mut self, incomplete: bool) -> AppResult<String> {
+        if let Some(id) = &self.id {
+            return Ok(id.clone());
+        }
+        let Some(mut turn) = self.turn.take() else {
+            return Err(AppError::new_plain("storing the turn failed earlier"));
+        };
+        turn.answer_date = store::now();
+        turn.incomplete = incomplete;
+        let id = tokio::spawn(save_turn(turn))
+            .await
+            .map_err(|e| AppError::new("storing the turn", e))??;
+        self.id = Some(id.clone());
+        Ok(id)
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if let Some(mut turn) = self.turn.take() {
+            turn.answer_date = store::now();
+            turn.incomplete = true;
+            tokio::spawn(async move {
+                if let Err(e) = save_turn(turn).await {
+                    tracing::error!("{:#?}", e);
+                }
+            });
+        }
+    }
+}
+
+/// Like `answer`, but streams the answer as the LLM writes it. The turn is
+/// stored once the LLM is done, or marked incomplete if the stream fails or is
+/// dropped first.
+pub async fn stream(
+    chat: String,
+    q: Question,
+    ip: Option<String>,
+    visitor: Option<String>,
+    tenant: Tenant,
+) -> AppResult<(Vec<Citation>, impl Stream<Item = Progress> + Send + 'static)> {
+    let (messages, citations) = prompt(&chat, &q.text, &tenant).await?;
+    let question_date = q.date.unwrap_or_else(store::now);
+    let tokens = Box::pin(llm::stream(&messages).await?);
+    let pending = Pending {
+        turn: Some(Turn {
+            chat,
+            page: q.page,
+            ip,
+            visitor,
+            tenant: tenant.0,
+            question: q.text,
+            question_date,
+            answer: String::new(),
+            answer_date: Value::Null,
+            incomplete: false,
+        }),
+        id: None,
+    };
+    let progress = stream::unfold(Some((pending, tokens)), |state| async move {
+        let (mut pending, mut tokens) = state?;
+        match tokens.next().await {
+            Some(Ok(token)) => {
+                if let Some(turn) = &mut pending.turn {
+                    turn.answer.push_str(&token);
+                }
+                Some((Progress::Token(token), Some((pending, tokens))))
+            }
+            Some(Err(e)) => {
+                tracing::error!("{:#?}", e);
+                if let Err(e) = pending.finish(true).await {
+                    tracing::error!("{:#?}", e);
+                }
+                Some((Progress::Failed, None))
+            }
+            None => match pending.finish(false).await {
+                Ok(id) => Some((Progress::Done(id), None)),
+                Err(e) => {
+                    tracing::error!("{:#?}", e);
+                    Some((Progress::Failed, None))
+                }
+            },
+        }
+    });
+    Ok((citations, progress))
+}
@@ -0,0 +1,104 @@
+//! Fixed-window limits on how often one client may call a route, for routes
+//! that cost money on every call, and on failed console sign-ins, to slow down
+//! guessing the admin key. Clients are told apart by address, IPv6 ones by
+//! their /64, which is what a single subscriber usually gets.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use warp::{Filter, Rejection};
+
+use crate::{client_ip::client_ip, metrics::RATE_LIMITED};
+
+/// Rejection for a client over its limit.
+#[derive(Debug)]
+pub struct TooManyRequests;
+
+impl warp::reject::Reject for TooManyRequests {}
+
+/// Clients tracked before those whose window has run out are swept.
+const SWEEP_AT: usize = 10_000;
+
+pub struct Limiter {
+    name: &'static str,
+    limit: u32,
+    window: Duration,
+    clients: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl Limiter {
+    pub fn new(name: &'static str, limit: u32, window: Duration) -> Self {
+        Limiter {
+            name,
+            limit,
+            window,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a call from `client`, returning whether it is within the limit.
+    pub fn allow(&self, client: IpAddr) -> bool {
+        let client = subscriber(client);
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= SWEEP_AT {
+            clients.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+        let (start, calls) = clients.entry(client).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *calls = 0;
+        }
+        *calls += 1;
+        *calls <= self.limit
+    }
+
+    /// Whether `client` has used up its calls in the current window. Unlike
+    /// `allow`, this doesn't count as a call.
+    pub fn exhausted(&self, client: IpAddr) -> bool {
+        let now = Instant::now();
+        self.clients
+            .lock()
+            .unwrap()
+            .get(&subscriber(client))
+            .is_some_and(|(start, calls)| {
+                now.duration_since(*start) < self.window && *calls >= self.limit
+            })
+    }
+}
+
+/// The key `client` is counted under: its /64 for IPv6, else its address.
+fn subscriber(client: IpAddr) -> IpAddr {
+    match client {
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & (u128::MAX << 64))),
+        ip => ip,
+    }
+}
+
+/// LLM answers, streamed or not, per client per minute.
+pub static REPLY_LIMITER: Lazy<Limiter> =
+    Lazy::new(|| Limiter::new("reply", 10, Duration::from_secs(60)));
+
+/// Failed console sign-ins per client per 15 minutes.
+pub static LOGIN_LIMITER: Lazy<Limiter> =
+    Lazy::new(|| Limiter::new("login", 10, Duration::from_secs(15 * 60)));
+
+/// Rejects with `TooManyRequests` once the client is over `limiter`'s limit.
+/// Requests without a known address share one allowance.
+pub fn limited(limiter: &'static Limiter) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    client_ip()
+        .and_then(move |ip: Option<IpAddr>| async move {
+            if limiter.allow(ip.unwrap_or(IpAddr::from([0, 0, 0, 0]))) {
+                Ok(())
+            } else {
+                RATE_LIMITED.with_label_values(&[limiter.name]).inc();
+                Err(warp::reject::custom(TooManyRequests))
+            }
+        })
+        .untuple_one()
+}
@@ -0,0 +1,204 @@
+use std::{
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use rand::Rng;
+
+use crate::{
+    app::{AppError, AppResult},
+    metrics::{CIRCUIT_OPEN, CIRCUIT_REJECTIONS, UPSTREAM_RETRIES},
+};
+
+/// Consecutive transient failures after which a breaker opens.
+const FAILURE_THRESHOLD: u32 = 5;
+/// How long an open breaker rejects calls before letting a probe through.
+const COOLDOWN: Duration = Duration::from_secs(30);
+const BASE_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+/// HTTP statuses worth retrying: the upstream may well answer the same
+/// request successfully a moment later.
+pub const TRANSIENT_STATUSES: &[u16] = &[408, 429, 500, 502, 503, 504];
+
+pub static QDRANT_BREAKER: Lazy<Breaker> = Lazy::new(|| Breaker::new("qdrant"));
+pub static EMBEDDING_BREAKER: Lazy<Breaker> = Lazy::new(|| Breaker::new("embedding"));
+pub static LLM_BREAKER: Lazy<Breaker> = Lazy::new(|| Breaker::new("llm"));
+
+#[derive(Clone, Copy, Debug)]
+pub struct Policy {
+    pub timeout: Duration,
+    pub retries: u32,
+    /// Only idempotent calls are retried; anything else is attempted once.
+    pub idempotent: bool,
+}
+
+/// A failed attempt, and whether trying again could help.
+pub struct Failure {
+    pub error: AppError,
+    pub transient: bool,
+}
+
+impl Failure {
+    pub fn transient(error: AppError) -> Self {
+        Failure {
+            error,
+            transient: true,
+        }
+    }
+
+    pub fn permanent(error: AppError) -> Self {
+        Failure {
+            error,
+            transient: false,
+        }
+    }
+
+    /// Connection problems and timeouts are transient; a response we could
+    /// not decode is not.
+    pub fn from_reqwest(m: &str, e: reqwest::Error) -> Self {
+        let transient = e.is_connect() || e.is_timeout() || e.is_request();
+        Failure {
+            error: AppError::new(m, e),
+            transient,
+        }
+    }
+
+    pub fn from_status(m: &str, status: reqwest::StatusCode, body: &str) -> Self {
+        Failure {
+            error: AppError::new_plain(&format!("{}: {} {}", m, status, body)),
+            transient: TRANSIENT_STATUSES.contains(&status.as_u16()),
+        }
+    }
+}
+
+struct State {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Fails fast once an upstream has failed `FAILURE_THRESHOLD` times in a row,
+/// then lets a single probe through every `COOLDOWN` until one succeeds.
+pub struct Breaker {
+    name: &'static str,
+    state: Mutex<State>,
+}
+
+impl Breaker {
+    pub fn new(name: &'static str) -> Self {
+        CIRCUIT_OPEN.with_label_values(&[name]).set(0);
+        Breaker {
+            name,
+            state: Mutex::new(State {
+                failures: 0,
+                open_until: None,
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Whether calls are being failed fast. Once the cooldown is over the
+    /// breaker is half-open, letting a probe through, and no longer counts as
+    /// open.
+    pub fn is_open(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .open_until
+            .is_some_and(|t| Instant::now() < t)
+    }
+
+    fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.open_until {
+            None => true,
+            Some(t) if Instant::now() >= t => {
+                // half-open: this caller probes, everyone else keeps failing fast
+                state.open_until = Some(Instant::now() + COOLDOWN);
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    fn success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = 0;
+        if state.open_until.take().is_some() {
+            tracing::info!(upstream = self.name, "circuit closed");
+            CIRCUIT_OPEN.with_label_values(&[self.name]).set(0);
+        }
+    }
+
+    fn failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        if state.failures >= FAILURE_THRESHOLD {
+            if state.open_until.is_none() {
+                tracing::warn!(upstream = self.name, "circuit opened");
+            }
+            state.open_until = Some(Instant::now() + COOLDOWN);
+            CIRCUIT_OPEN.with_label_values(&[self.name]).set(1);
+        }
+    }
+}
+
+/// Full-jitter exponential backoff for the given (zero-based) retry.
+fn backoff(attempt: u32) -> Duration {
+    let cap = BASE_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF);
+    rand::thread_rng().gen_range(Duration::ZERO..=cap)
+}
+
+/// Runs `f` under `policy` behind `breaker`, retrying transient failures of
+/// idempotent calls.
+pub async fn call<T, F, Fut>(breaker: &Breaker, policy: Policy, mut f: F) -> AppResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Failure>>,
+{
+    let mut attempt = 0;
+    loop {
+        if !breaker.allow() {
+            CIRCUIT_REJECTIONS.with_label_values(&[breaker.name]).inc();
+            return Err(AppError::new_plain(&format!(
+                "{} circuit open, failing fast",
+                breaker.name
+            )));
+        }
+        let res = match tokio::time::timeout(policy.timeout, f()).await {
+            Ok(res) => res,
+            Err(_) => Err(Failure::transient(AppError::new_plain(&format!(
+                "{} timed out after {:?}",
+                breaker.name, policy.timeout
+            )))),
+        };
+        match res {
+            Ok(v) => {
+                breaker.success();
+                return Ok(v);
+            }
+            Err(e) if e.transient => {
+                breaker.failure();
+                if !policy.idempotent || attempt >= policy.retries {
+                    return Err(e.error);
+                }
+                tracing::warn!(upstream = breaker.name, attempt, error = %e.error, "retrying");
+                UPSTREAM_RETRIES.with_label_values(&[breaker.name]).inc();
+                tokio::time::sleep(backoff(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                // the upstream answered, so it is up even if it refused us
+                breaker.success();
+                return Err(e.error);
+            }
+        }
+    }
+}
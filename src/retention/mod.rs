@@ -0,0 +1,288 @@
+//! Retention policy: chat messages are deleted, or stripped of their client
+//! address, once their `d` timestamp is old enough. Configured per category
+//! with `RETENTION` (delete) and `RETENTION_IP` (strip `a` and the `g`
+//! location derived from it), both lists of `category:days` such as
+//! `m:365,scm:90`; `RETENTION_INTERVAL_MINUTES` sets how often the background
+//! purge runs and `RETENTION_DRY_RUN=true` makes it only report.
+//!
+//! Matching uses range filters, which only cover numeric `d` values. Each run
+//! therefore first rewrites numeric and RFC 3339 strings, which clients send,
+//! as numbers, and reports how many points carry a `d` that can't be read as a
+//! time at all.
+
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    analytics,
+    app::AppResult,
+    constants::{COLLECTION, SECRETS},
+    conversation,
+    erasure::chats,
+    faq,
+    qdrant::{qdrant_path, qdrant_post},
+    store,
+    transcript::{date_range, timestamp},
+    util,
+};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Delete,
+    StripIp,
+}
+
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub category: String,
+    pub action: Action,
+    pub days: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Policy {
+    pub rules: Vec<Rule>,
+    pub interval: Duration,
+    pub dry_run: bool,
+}
+
+fn rules(spec: &str, action: Action) -> Vec<Rule> {
+    spec.split(',')
+        .filter_map(|r| {
+            let (category, days) = r.split_once(':')?;
+            let Ok(days) = days.trim().parse() else {
+                tracing::warn!(rule = r, "ignoring retention rule with bad day count");
+                return None;
+            };
+            Some(Rule {
+                category: category.trim().to_string(),
+                action,
+                days,
+            })
+        })
+        .collect()
+}
+
+pub async fn policy() -> Policy {
+    let secrets = SECRETS.lock().await;
+    let mut r = rules(
+        &secrets.get("RETENTION").unwrap_or_default(),
+        Action::Delete,
+    );
+    r.extend(rules(
+        &secrets.get("RETENTION_IP").unwrap_or_default(),
+        Action::StripIp,
+    ));
+    Policy {
+        rules: r,
+        interval: secrets
+            .get("RETENTION_INTERVAL_MINUTES")
+            .and_then(|m| m.parse().ok())
+            .map_or(DEFAULT_INTERVAL, |m: u64| Duration::from_secs(m * 60)),
+        dry_run: secrets.get("RETENTION_DRY_RUN").as_deref() == Some("true"),
+    }
+}
+
+#[derive(Serialize)]
+pub struct Outcome {
+    pub category: String,
+    pub action: Action,
+    pub older_than: DateTime<Utc>,
+    /// Points affected, or that would be in a dry run.
+    pub points: u64,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub dry_run: bool,
+    /// String `d` values rewritten as numbers, or that would be in a dry run.
+    /// A dry run counts these points with neither rule.
+    pub normalized: u64,
+    /// Points of a ruled category whose `d` isn't a time, which no rule can
+    /// match.
+    pub unmatched: u64,
+    pub outcomes: Vec<Outcome>,
+}
+
+/// Rewrites non-numeric `d` values of `category` that read as a time as
+/// numbers, one batch of payload updates per page. Returns how many were, or
+/// would be, rewritten and how many can't be.
+async fn normalize(category: &str, dry_run: bool) -> AppResult<(u64, u64)> {
+    let (mut normalized, mut unmatched) = (0, 0);
+    let mut offset = Value::Null;
+    loop {
+        let mut body = json!({
+            "limit": 256,
+            "with_payload": ["d"],
+            "filter": {
+                "must": [{"key": "c", "match": {"value": category}}],
+                "must_not": [{"is_empty": {"key": "d"}}, date_range(None, None)],
+            },
+        });
+        if !offset.is_null() {
+            body["offset"] = offset;
+        }
+        let res = qdrant_post(
+            &qdrant_path(&format!("collections/{}/points/scroll", COLLECTION)).await?,
+            body,
+        )
+        .await?;
+        let mut operations = vec![];
+        for p in res["result"]["points"].as_array().into_iter().flatten() {
+            let d = &p["payload"]["d"];
+            let (Some(_), Some(s)) = (timestamp(d), d.as_str()) else {
+                unmatched += 1;
+                continue;
+            };
+            operations.push(json!({"set_payload": {
+                "payload": {"d": store::date(s.to_string())},
+                "points": [p["id"]],
+            }}));
+        }
+        normalized += operations.len() as u64;
+        if !dry_run && !operations.is_empty() {
+            qdrant_post(
+                &qdrant_path(&format!(
+                    "collections/{}/points/batch?wait=true",
+                    COLLECTION
+                ))
+                .await?,
+                json!({ "operations": operations }),
+            )
+            .await?;
+        }
+        offset = res["result"]["next_page_offset"].clone();
+        if offset.is_null() {
+            break;
+        }
+    }
+    Ok((normalized, unmatched))
+}
+
+/// Points of `rule`'s category dated before `cutoff`, whether `d` is in
+/// seconds or milliseconds. Stripping only looks at points still holding an
+/// address or the location looked up from it.
+fn filter(rule: &Rule, cutoff: DateTime<Utc>) -> Value {
+    let mut f = json!({"must": [
+        {"key": "c", "match": {"value": rule.category}},
+        date_range(None, Some(cutoff)),
+    ]});
+    if rule.action == Action::StripIp {
+        f["must"].as_array_mut().unwrap().push(json!({"should": [
+            {"must_not": [{"is_empty": {"key": "a"}}]},
+            {"must_not": [{"is_empty": {"key": "ip"}}]},
+            {"must_not": [{"is_empty": {"key": "g"}}]},
+        ]}));
+    }
+    f
+}
+
+async fn apply(rule: &Rule, dry_run: bool) -> AppResult<Outcome> {
+    let older_than = Utc::now() - TimeDelta::days(rule.days.into());
+    let filter = filter(rule, older_than);
+    let points = qdrant_post(
+        &qdrant_path(&format!("collections/{}/points/count", COLLECTION)).await?,
+        json!({"filter": filter, "exact": true}),
+    )
+    .await?["result"]["count"]
+        .as_u64()
+        .unwrap_or(0);
+    if !dry_run && points > 0 {
+        match rule.action {
+            Action::Delete => {
+                let touched = chats(&filter).await?;
+                qdrant_post(
+                    &qdrant_path(&format!(
+                        "collections/{}/points/delete?wait=true",
+                        COLLECTION
+                    ))
+                    .await?,
+                    json!({ "filter": filter }),
+                )
+                .await?;
+                // their vectors still average the deleted messages
+                conversation::forget(&touched).await?;
+                analytics::forget();
+                faq::forget().await;
+                util::forget_embeddings().await;
+            }
+            Action::StripIp => {
+                qdrant_post(
+                    &qdrant_path(&format!(
+                        "collections/{}/points/payload/delete?wait=true",
+                        COLLECTION
+                    ))
+                    .await?,
+                    json!({"keys": ["a", "ip", "g"], "filter": filter}),
+                )
+                .await?;
+            }
+        }
+    }
+    Ok(Outcome {
+        category: rule.category.clone(),
+        action: rule.action,
+        older_than,
+        points,
+    })
+}
+
+/// Applies every rule of `policy` once. Deletes run before strips so points
+/// about to go aren't rewritten first.
+pub async fn run(policy: &Policy, dry_run: bool) -> AppResult<Report> {
+    let mut rules = policy.rules.clone();
+    rules.sort_by_key(|r| r.action != Action::Delete);
+    let mut categories: Vec<&str> = rules.iter().map(|r| r.category.as_str()).collect();
+    categories.sort_unstable();
+    categories.dedup();
+    let (mut normalized, mut unmatched) = (0, 0);
+    for category in categories {
+        let (n, u) = normalize(category, dry_run).await?;
+        if u > 0 {
+            tracing::warn!(category, points = u, "retention can't date these points");
+        }
+        normalized += n;
+        unmatched += u;
+    }
+    let mut outcomes = vec![];
+    for rule in &rules {
+        let o = apply(rule, dry_run).await?;
+        tracing::info!(
+            category = %o.category,
+            action = ?o.action,
+            points = o.points,
+            dry_run,
+            "retention"
+        );
+        outcomes.push(o);
+    }
+    Ok(Report {
+        dry_run,
+        normalized,
+        unmatched,
+        outcomes,
+    })
+}
+
+/// Starts the periodic purge when any rule is configured.
+pub async fn spawn() {
+    let policy = policy().await;
+    if policy.rules.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(policy.interval);
+        loop {
+            tick.tick().await;
+            if let Err(e) = run(&policy, policy.dry_run).await {
+                tracing::error!("{:#?}", e);
+            }
+        }
+    });
+}
@@ -1,10 +1,10 @@
-use serde_json::json;
 use warp::reply::Reply;
 
 use crate::{
     app::AppResult,
-    qdrant::{qdrant_path, qdrant_put},
-    util::{embedding, id},
+    store::{date, save_turn, Turn},
+    tenant::Tenant,
+    visitor::Visitor,
 };
 
 #[derive(serde::Deserialize)]
@@ -17,35 +17,37 @@ pub struct Add {
     p: String,
 }
 
-pub async fn add(s: Add, addr: Option<std::net::SocketAddr>) -> impl Reply {
-    f(s, addr).await.map_or_else(
+pub async fn add(s: Add, ip: Option<std::net::IpAddr>, v: Visitor, t: Tenant) -> impl Reply {
+    let reply = f(s, ip, v.id.clone(), t).await.map_or_else(
         |e| {
-            log::error!("{:#?}", e);
+            tracing::error!("{:#?}", e);
             warp::reply::with_status(
                 "An error occured on our side".to_string(),
                 warp::http::StatusCode::INTERNAL_SERVER_ERROR,
             )
         },
         |v| warp::reply::with_status(v, warp::http::StatusCode::OK),
-    )
+    );
+    v.reply(reply)
 }
 
-async fn f(s: Add, addr: Option<std::net::SocketAddr>) -> AppResult<String> {
-    let id = id().await?;
-    if let Some(a) = addr {
-        qdrant_put(
-            &qdrant_path("collections/i/points").await?,
-            json!({"points":[{"id":id, "payload": {"u": 1, "a": a.ip().to_string(), "m": s.u, "c": "m", "i": s.i, "p": s.p, "d": s.ud}, "vector": embedding(&s.u).await? }]}),
-        ).await?;
-    } else {
-        qdrant_put(
-            &qdrant_path("collections/i/points").await?,
-            json!({"points":[{"id":id, "payload": {"u": 1, "m": s.u, "c": "m", "i": s.i, "p": s.p, "d": s.ud}, "vector": embedding(&s.u).await? }]}),
-        ).await?;
-    }
-    qdrant_put(
-        &qdrant_path("collections/i/points").await?,
-        json!({"points":[{"id":id, "payload": {"u": 0, "m": s.a, "c": "m", "i": s.i, "p": s.p, "d": s.ad}, "vector": embedding(&s.a).await? }]}),
-    ).await?;
-    Ok(id)
+async fn f(
+    s: Add,
+    ip: Option<std::net::IpAddr>,
+    visitor: Option<String>,
+    tenant: Tenant,
+) -> AppResult<String> {
+    save_turn(Turn {
+        chat: s.i,
+        page: Some(s.p),
+        ip: ip.map(|a| a.to_string()),
+        visitor,
+        tenant: tenant.0,
+        question: s.u,
+        question_date: date(s.ud),
+        answer: s.a,
+        answer_date: date(s.ad),
+        incomplete: false,
+    })
+    .await
 }
@@ -0,0 +1,25 @@
+use warp::reply::Reply;
+
+use crate::analytics::{run, Options};
+
+pub async fn analytics(o: Options) -> impl Reply {
+    let tz = match o.check() {
+        Ok(tz) => tz,
+        Err(e) => {
+            return warp::reply::with_status(
+                warp::reply::json(&e),
+                warp::http::StatusCode::BAD_REQUEST,
+            )
+        }
+    };
+    run(tz, &o).await.map_or_else(
+        |e| {
+            tracing::error!("{:#?}", e);
+            warp::reply::with_status(
+                warp::reply::json(&"An error occured on our side"),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        },
+        |r| warp::reply::with_status(warp::reply::json(&r), warp::http::StatusCode::OK),
+    )
+}
@@ -0,0 +1,59 @@
+use futures_util::{Stream, StreamExt};
+use serde::Deserialize;
+use warp::reply::Reply;
+
+use crate::{
+    analytics,
+    backup::{self, ImportOptions},
+    constants::COLLECTION,
+    util::ndjson_lines,
+};
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    vectors: bool,
+}
+
+pub async fn export(q: ExportQuery) -> impl Reply {
+    let body = backup::export(q.vectors)
+        .map(|chunk| chunk.inspect_err(|e| tracing::error!("export aborted: {:#?}", e)));
+    let mut res = warp::reply::Response::new(warp::hyper::Body::wrap_stream(body));
+    let headers = res.headers_mut();
+    headers.insert(
+        "Content-Type",
+        warp::http::HeaderValue::from_static("application/x-ndjson"),
+    );
+    if let Ok(v) = warp::http::HeaderValue::from_str(&format!(
+        "attachment; filename=\"{}-{}.ndjson\"",
+        COLLECTION,
+        timestamp()
+    )) {
+        headers.insert("Content-Disposition", v);
+    }
+    res
+}
+
+/// Seconds since the epoch, enough to tell backups apart.
+fn timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub async fn import<S, B>(q: ImportOptions, body: S) -> impl Reply
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: warp::Buf,
+{
+    let report = backup::import(ndjson_lines(body), q).await;
+    // cached days don't have the restored messages
+    analytics::forget();
+    let status = if report.error.is_some() {
+        warp::http::StatusCode::INTERNAL_SERVER_ERROR
+    } else {
+        warp::http::StatusCode::OK
+    };
+    warp::reply::with_status(warp::reply::json(&report), status)
+}
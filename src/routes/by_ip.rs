@@ -0,0 +1,12 @@
+use serde::Deserialize;
+
+use crate::{search::visitors, tenant::Tenant};
+
+#[derive(Deserialize)]
+pub struct ByIP {
+    d: i64,
+}
+
+pub async fn handle_by_ip(q: ByIP, t: Tenant) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&visitors(q.d, &t).await?))
+}
@@ -4,12 +4,13 @@ use warp::reply::Reply;
 use crate::{
     constants::AppResult,
     qdrant::{qdrant_path, qdrant_post},
+    tenant::Tenant,
 };
 
-pub async fn chat(id: String) -> impl Reply {
-    f(id).await.map_or_else(
+pub async fn chat(id: String, t: Tenant) -> impl Reply {
+    f(id, t).await.map_or_else(
         |e| {
-            log::error!("{:#?}", e);
+            tracing::error!("{:#?}", e);
             warp::reply::with_status(
                 "An error occured on our side".to_string(),
                 warp::http::StatusCode::INTERNAL_SERVER_ERROR,
@@ -19,10 +20,10 @@ pub async fn chat(id: String) -> impl Reply {
     )
 }
 
-pub async fn f(id: String) -> AppResult<String> {
+pub async fn f(id: String, t: Tenant) -> AppResult<String> {
     Ok(qdrant_post(
         &qdrant_path("collections/i/points/scroll").await?,
-        json!({"limit": 7,  "filter": {"must": [{"key": "c", "match": {"value": "scm"}}, {"key": "i", "match": {"value": id}}]}}),
+        json!({"limit": 7,  "filter": t.scope(Some(json!({"must": [{"key": "c", "match": {"value": "scm"}}, {"key": "i", "match": {"value": id}}]})))}),
     )
     .await?["result"]["points"]
         .to_string())
@@ -0,0 +1,48 @@
+use serde::Deserialize;
+use warp::reply::Reply;
+
+use crate::{
+    tenant::Tenant,
+    transcript::{load, Format},
+};
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: Format,
+}
+
+/// Admin only, so the chat is looked up across tenants.
+pub async fn chat_export(id: String, q: ExportQuery) -> warp::reply::Response {
+    match load(&id, &Tenant::ALL).await {
+        Ok(messages) => {
+            let filename: String = id
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+                .collect();
+            let reply = warp::reply::with_header(
+                q.format.render(&id, &messages),
+                "Content-Type",
+                q.format.content_type(),
+            );
+            warp::reply::with_header(
+                reply,
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"chat-{}.{}\"",
+                    filename,
+                    q.format.extension()
+                ),
+            )
+            .into_response()
+        }
+        Err(e) => {
+            tracing::error!("{:#?}", e);
+            warp::reply::with_status(
+                "An error occured on our side".to_string(),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response()
+        }
+    }
+}
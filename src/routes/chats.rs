@@ -4,12 +4,13 @@ use warp::reply::Reply;
 use crate::{
     constants::AppResult,
     qdrant::{qdrant_path, qdrant_post},
+    tenant::Tenant,
 };
 
-pub async fn chats() -> impl Reply {
-    f().await.map_or_else(
+pub async fn chats(t: Tenant) -> impl Reply {
+    f(t).await.map_or_else(
         |e| {
-            log::error!("{:#?}", e);
+            tracing::error!("{:#?}", e);
             warp::reply::with_status(
                 "An error occured on our side".to_string(),
                 warp::http::StatusCode::INTERNAL_SERVER_ERROR,
@@ -19,12 +20,15 @@ pub async fn chats() -> impl Reply {
     )
 }
 
-pub async fn f() -> AppResult<String> {
+pub async fn f(t: Tenant) -> AppResult<String> {
     let res = qdrant_post(
         &qdrant_path("collections/i/points/scroll").await?,
-        json!({"limit": 7, "filter": {"must": [{"key": "c", "match": {"value": "lucid"}}]}}),
+        json!({"limit": 7, "filter": t.scope(Some(json!({"must": [{"key": "c", "match": {"value": "lucid"}}]})))}),
     )
     .await?;
-    println!("chats res: {}", res);
+    tracing::debug!(
+        points = res["result"]["points"].as_array().map_or(0, Vec::len),
+        "chats"
+    );
     Ok(res["result"]["points"].to_string())
 }
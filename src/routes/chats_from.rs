@@ -4,12 +4,13 @@ use warp::reply::Reply;
 use crate::{
     constants::AppResult,
     qdrant::{qdrant_path, qdrant_post},
+    tenant::Tenant,
 };
 
-pub async fn chats_from(from: i64) -> impl Reply {
-    f(from).await.map_or_else(
+pub async fn chats_from(from: i64, t: Tenant) -> impl Reply {
+    f(from, t).await.map_or_else(
         |e| {
-            log::error!("{:#?}", e);
+            tracing::error!("{:#?}", e);
             warp::reply::with_status(
                 "An error occured on our side".to_string(),
                 warp::http::StatusCode::INTERNAL_SERVER_ERROR,
@@ -19,12 +20,15 @@ pub async fn chats_from(from: i64) -> impl Reply {
     )
 }
 
-pub async fn f(from: i64) -> AppResult<String> {
+pub async fn f(from: i64, t: Tenant) -> AppResult<String> {
     let res = qdrant_post(
         &qdrant_path("collections/i/points/scroll").await?,
-        json!({"limit": 7, "offset": (from - 1) * 7, "filter": {"must": [{"key": "c", "match": {"value": "lucid"}}]}}),
+        json!({"limit": 7, "offset": (from - 1) * 7, "filter": t.scope(Some(json!({"must": [{"key": "c", "match": {"value": "lucid"}}]})))}),
     )
     .await?;
-    println!("chats res: {}", res);
+    tracing::debug!(
+        points = res["result"]["points"].as_array().map_or(0, Vec::len),
+        "chats"
+    );
     Ok(res["result"]["points"].to_string())
 }
@@ -0,0 +1,164 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use warp::{
+    http::{header, StatusCode},
+    reply::{html, Reply, Response},
+};
+
+use crate::{
+    app::AppResult,
+    auth::{is_admin_key, session_token, SESSION_COOKIE, SESSION_TTL},
+    console,
+    erasure::{erase, Subject},
+    metrics::RATE_LIMITED,
+    ratelimit::LOGIN_LIMITER,
+    routes::chat_export::{chat_export, ExportQuery},
+    search::{semantic, visitors},
+    tenant::Tenant,
+    transcript,
+};
+
+fn sign_in() -> Response {
+    warp::reply::with_status(html(console::login(None)), StatusCode::UNAUTHORIZED).into_response()
+}
+
+fn render(page: AppResult<String>) -> Response {
+    page.map_or_else(
+        |e| {
+            tracing::error!("{:#?}", e);
+            warp::reply::with_status(
+                html(console::page(
+                    "Error",
+                    "<p class=\"error\">An error occured on our side</p>",
+                )),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response()
+        },
+        |p| html(p).into_response(),
+    )
+}
+
+fn redirect(to: &str, cookie: Option<String>) -> Response {
+    let reply = warp::reply::with_header(StatusCode::SEE_OTHER, header::LOCATION, to);
+    match cookie {
+        Some(c) => warp::reply::with_header(reply, header::SET_COOKIE, c).into_response(),
+        None => reply.into_response(),
+    }
+}
+
+fn session_cookie(value: &str, max_age: i64) -> String {
+    format!(
+        "{}={}; Path=/admin; Max-Age={}; HttpOnly; Secure; SameSite=Strict",
+        SESSION_COOKIE, value, max_age
+    )
+}
+
+/// Signs in with the admin key. Failed attempts count against the client's
+/// `LOGIN_LIMITER` allowance, and once it is used up the key isn't checked.
+pub async fn login(form: HashMap<String, String>, ip: Option<IpAddr>) -> Response {
+    let client = ip.unwrap_or(IpAddr::from([0, 0, 0, 0]));
+    if LOGIN_LIMITER.exhausted(client) {
+        RATE_LIMITED.with_label_values(&["login"]).inc();
+        return warp::reply::with_status(
+            html(console::login(Some("Too many attempts, try again later"))),
+            StatusCode::TOO_MANY_REQUESTS,
+        )
+        .into_response();
+    }
+    let key = form.get("key").map(String::as_str).unwrap_or_default();
+    if !is_admin_key(key).await {
+        LOGIN_LIMITER.allow(client);
+        return warp::reply::with_status(
+            html(console::login(Some("Wrong key"))),
+            StatusCode::UNAUTHORIZED,
+        )
+        .into_response();
+    }
+    redirect(
+        "/admin",
+        session_token()
+            .await
+            .map(|token| session_cookie(&token, SESSION_TTL)),
+    )
+}
+
+pub async fn logout() -> Response {
+    redirect("/admin", Some(session_cookie("", 0)))
+}
+
+pub async fn index(q: HashMap<String, String>, signed_in: bool) -> Response {
+    if !signed_in {
+        return sign_in();
+    }
+    let country = q
+        .get("country")
+        .map(|c| c.trim().to_uppercase())
+        .filter(|c| !c.is_empty());
+    render(
+        console::conversations(country.as_deref())
+            .await
+            .map(|chats| console::conversations_page(&chats, country.as_deref())),
+    )
+}
+
+pub async fn chat(id: String, signed_in: bool) -> Response {
+    if !signed_in {
+        return sign_in();
+    }
+    let id = console::decode_segment(&id);
+    render(
+        transcript::load(&id, &Tenant::ALL)
+            .await
+            .map(|messages| console::chat_page(&id, &messages)),
+    )
+}
+
+pub async fn export(id: String, q: ExportQuery, signed_in: bool) -> Response {
+    if !signed_in {
+        return sign_in();
+    }
+    let id = console::decode_segment(&id);
+    chat_export(id, q).await
+}
+
+pub async fn delete(id: String, signed_in: bool) -> Response {
+    if !signed_in {
+        return sign_in();
+    }
+    let id = console::decode_segment(&id);
+    match erase(Subject::Chat(id), Some("admin console".to_string())).await {
+        Ok(_) => redirect("/admin", None),
+        Err(e) => render(Err(e)),
+    }
+}
+
+pub async fn search(q: HashMap<String, String>, signed_in: bool) -> Response {
+    if !signed_in {
+        return sign_in();
+    }
+    let text = q.get("q").map(|s| s.trim()).unwrap_or_default();
+    let filter = q.get("f").map(String::as_str).unwrap_or_default();
+    if text.is_empty() {
+        return render(Ok(console::search_page(text, filter, None)));
+    }
+    render(
+        semantic(text, console::parse_filter(filter).as_ref(), &Tenant::ALL)
+            .await
+            .map(|results| console::search_page(text, filter, Some(&results))),
+    )
+}
+
+pub async fn visitors_page(q: HashMap<String, String>, signed_in: bool) -> Response {
+    if !signed_in {
+        return sign_in();
+    }
+    let Some(since) = q.get("d").and_then(|d| d.trim().parse().ok()) else {
+        return render(Ok(console::visitors_page(None, None)));
+    };
+    render(
+        visitors(since, &Tenant::ALL)
+            .await
+            .map(|groups| console::visitors_page(Some(since), Some(&groups))),
+    )
+}
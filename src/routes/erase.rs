@@ -0,0 +1,46 @@
+use serde::Deserialize;
+use warp::reply::Reply;
+
+use crate::{
+    erasure::{self, Subject},
+    privacy,
+};
+
+#[derive(Deserialize)]
+pub struct EraseOptions {
+    /// Why the data is erased, e.g. a ticket number; kept in the audit record.
+    reason: Option<String>,
+}
+
+pub async fn erase(kind: String, value: String, o: EraseOptions) -> warp::reply::Response {
+    let Some(subject) = Subject::parse(&kind, value) else {
+        return warp::reply::with_status(
+            warp::reply::json(&"Unknown subject, expected chat, ip or visitor"),
+            warp::http::StatusCode::NOT_FOUND,
+        )
+        .into_response();
+    };
+    if !subject.erasable(&privacy::config().await) {
+        return warp::reply::with_status(
+            warp::reply::json(
+                &"Addresses are stored truncated, erasing one would hit other visitors; erase by chat or visitor instead",
+            ),
+            warp::http::StatusCode::CONFLICT,
+        )
+        .into_response();
+    }
+    erasure::erase(subject, o.reason).await.map_or_else(
+        |e| {
+            tracing::error!("{:#?}", e);
+            warp::reply::with_status(
+                warp::reply::json(&"An error occured on our side"),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response()
+        },
+        |r| {
+            warp::reply::with_status(warp::reply::json(&r), warp::http::StatusCode::OK)
+                .into_response()
+        },
+    )
+}
@@ -0,0 +1,26 @@
+use warp::reply::Reply;
+
+use crate::faq::{self, Options};
+
+pub async fn run_faq(o: Options) -> impl Reply {
+    faq::run(o).await.map_or_else(
+        |e| {
+            tracing::error!("{:#?}", e);
+            warp::reply::with_status(
+                warp::reply::json(&"An error occured on our side"),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        },
+        |r| warp::reply::with_status(warp::reply::json(&r), warp::http::StatusCode::OK),
+    )
+}
+
+pub async fn latest_faq() -> impl Reply {
+    match faq::latest().await {
+        Some(r) => warp::reply::with_status(warp::reply::json(&r), warp::http::StatusCode::OK),
+        None => warp::reply::with_status(
+            warp::reply::json(&"Not Found"),
+            warp::http::StatusCode::NOT_FOUND,
+        ),
+    }
+}
@@ -0,0 +1,64 @@
+use futures_util::{stream, SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use warp::{
+    reply::Reply,
+    ws::{Message, WebSocket, Ws},
+};
+
+use crate::bus::{self, Event};
+
+#[derive(Deserialize)]
+pub struct FeedOptions {
+    /// Only forward events of this chat.
+    chat: Option<String>,
+    /// Only forward events of this tenant; admins see every tenant otherwise.
+    tenant: Option<String>,
+}
+
+enum Input {
+    Event(Event),
+    Lagged(u64),
+    Closed,
+}
+
+/// Upgrades to a WebSocket that forwards bus events as JSON text frames.
+pub async fn feed(ws: Ws, o: FeedOptions) -> impl Reply {
+    ws.on_upgrade(move |socket| serve(socket, o))
+}
+
+async fn serve(socket: WebSocket, o: FeedOptions) {
+    let (mut tx, rx) = socket.split();
+    let events = stream::unfold(bus::subscribe(), |mut events| async move {
+        match events.recv().await {
+            Ok(e) => Some((Input::Event(e), events)),
+            Err(RecvError::Lagged(n)) => Some((Input::Lagged(n), events)),
+            Err(RecvError::Closed) => None,
+        }
+    });
+    // the client only ever closes; anything else it sends is ignored
+    let client = rx
+        .take_while(|m| futures_util::future::ready(matches!(m, Ok(m) if !m.is_close())))
+        .filter_map(|_| futures_util::future::ready(None))
+        .chain(stream::once(async { Input::Closed }));
+    let mut inputs = Box::pin(stream::select(events, client));
+    while let Some(input) = inputs.next().await {
+        let frame = match input {
+            Input::Event(e) => {
+                if (o.chat.is_some() && e.chat() != o.chat.as_deref())
+                    || (o.tenant.is_some() && e.tenant() != o.tenant.as_deref())
+                {
+                    continue;
+                }
+                serde_json::to_string(&e).unwrap_or_default()
+            }
+            Input::Lagged(n) => json!({"type": "lagged", "skipped": n}).to_string(),
+            Input::Closed => break,
+        };
+        if tx.send(Message::text(frame)).await.is_err() {
+            break;
+        }
+    }
+    let _ = tx.close().await;
+}
@@ -0,0 +1,148 @@
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use warp::reply::Reply;
+
+use crate::{
+    app::AppError,
+    constants::{AppResult, COLLECTION, SECRETS},
+    qdrant::{qdrant_get, qdrant_path},
+    resilience::{Breaker, EMBEDDING_BREAKER, LLM_BREAKER, QDRANT_BREAKER},
+    util::fetch_embedding,
+};
+
+const REQUIRED_SECRETS: &[&str] = &["QDRANT_URL", "QDRANT_KEY", "EMBEDDING_URL"];
+
+/// How long a readiness result is served before dependencies are probed again.
+const PROBE_TTL: Duration = Duration::from_secs(5);
+
+/// The last probe and when it ran. Held while probing, so concurrent requests
+/// wait for one probe instead of each starting their own.
+static PROBE: Lazy<Mutex<Option<(Instant, Value)>>> = Lazy::new(Default::default);
+
+pub async fn healthz() -> impl Reply {
+    warp::reply::json(&json!({"status": "ok"}))
+}
+
+/// Reports whether each dependency passes. Results are cached for
+/// `PROBE_TTL`, so probes can't be used to load Qdrant or the embedding
+/// service; failures are only detailed in the logs.
+pub async fn readyz() -> impl Reply {
+    let mut cached = PROBE.lock().await;
+    let checks = match &*cached {
+        Some((at, checks)) if at.elapsed() < PROBE_TTL => checks.clone(),
+        _ => {
+            let checks = probe().await;
+            *cached = Some((Instant::now(), checks.clone()));
+            checks
+        }
+    };
+    drop(cached);
+    let ready = checks
+        .as_object()
+        .into_iter()
+        .flatten()
+        .all(|(_, c)| c == "ok");
+    warp::reply::with_status(
+        warp::reply::json(&json!({
+            "status": if ready { "ok" } else { "error" },
+            "checks": checks,
+            "circuits": {
+                QDRANT_BREAKER.name(): circuit(&QDRANT_BREAKER),
+                EMBEDDING_BREAKER.name(): circuit(&EMBEDDING_BREAKER),
+                LLM_BREAKER.name(): circuit(&LLM_BREAKER),
+            },
+        })),
+        if ready {
+            warp::http::StatusCode::OK
+        } else {
+            warp::http::StatusCode::SERVICE_UNAVAILABLE
+        },
+    )
+}
+
+/// Checks every dependency, returning `ok` or `error` for each.
+async fn probe() -> Value {
+    let secrets = secrets().await;
+    let (qdrant, size) = check("qdrant", qdrant()).await;
+    let expected = SECRETS
+        .lock()
+        .await
+        .get("VECTOR_SIZE")
+        .and_then(|s| s.parse::<u64>().ok())
+        .or(size);
+    let (embedding, _) = check("embedding", embedding(expected)).await;
+    json!({"secrets": secrets, "qdrant": qdrant, "embedding": embedding})
+}
+
+fn circuit(breaker: &Breaker) -> &'static str {
+    if breaker.is_open() {
+        "open"
+    } else {
+        "closed"
+    }
+}
+
+async fn secrets() -> &'static str {
+    let store = SECRETS.lock().await;
+    let missing: Vec<&str> = REQUIRED_SECRETS
+        .iter()
+        .filter(|k| store.get(k).unwrap_or_default().is_empty())
+        .copied()
+        .collect();
+    if missing.is_empty() {
+        "ok"
+    } else {
+        tracing::warn!(?missing, "readiness: secrets missing");
+        "error"
+    }
+}
+
+/// Runs a dependency check, logging why it failed.
+async fn check<T>(
+    name: &str,
+    f: impl std::future::Future<Output = AppResult<T>>,
+) -> (&'static str, Option<T>) {
+    let start = Instant::now();
+    let res = f.await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+    match res {
+        Ok(v) => ("ok", Some(v)),
+        Err(e) => {
+            tracing::warn!(dependency = name, latency_ms, error = %e, "readiness check failed");
+            ("error", None)
+        }
+    }
+}
+
+/// Verifies the collection exists and returns its configured vector size.
+async fn qdrant() -> AppResult<u64> {
+    let res = qdrant_get(&qdrant_path(&format!("collections/{}", COLLECTION)).await?).await?;
+    if res["status"] != "ok" {
+        return Err(AppError::new_plain(&format!(
+            "collection {} not available: {}",
+            COLLECTION, res["status"]
+        )));
+    }
+    res["result"]["config"]["params"]["vectors"]["size"]
+        .as_u64()
+        .ok_or(AppError::new_plain("collection has no unnamed vector size"))
+}
+
+/// Embeds a probe string and checks the returned dimension.
+async fn embedding(expected: Option<u64>) -> AppResult<u64> {
+    let size = fetch_embedding("readiness probe")
+        .await?
+        .as_array()
+        .map(|v| v.len() as u64)
+        .ok_or(AppError::new_plain("embedding response has no vector"))?;
+    match expected {
+        Some(e) if e != size => Err(AppError::new_plain(&format!(
+            "embedding dimension {} does not match vector size {}",
+            size, e
+        ))),
+        _ => Ok(size),
+    }
+}
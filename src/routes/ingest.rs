@@ -0,0 +1,20 @@
+use futures_util::Stream;
+use warp::reply::Reply;
+
+use crate::{analytics, ingest::ingest as run, tenant::Tenant, util::ndjson_lines};
+
+pub async fn ingest<S, B>(t: Tenant, body: S) -> impl Reply
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: warp::Buf,
+{
+    let report = run(ndjson_lines(body), &t).await;
+    // cached days don't have the backfilled messages
+    analytics::forget();
+    let status = if report.failed == 0 {
+        warp::http::StatusCode::OK
+    } else {
+        warp::http::StatusCode::MULTI_STATUS
+    };
+    warp::reply::with_status(warp::reply::json(&report), status)
+}
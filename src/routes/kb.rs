@@ -0,0 +1,43 @@
+use serde_json::json;
+use warp::reply::Reply;
+
+use crate::{
+    kb::{self, Document},
+    tenant::Tenant,
+};
+
+pub async fn put_document(id: String, t: Tenant, doc: Document) -> impl Reply {
+    kb::put(&id, doc, &t).await.map_or_else(
+        |e| {
+            tracing::error!("{:#?}", e);
+            warp::reply::with_status(
+                warp::reply::json(&"An error occured on our side"),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        },
+        |n| {
+            warp::reply::with_status(
+                warp::reply::json(&json!({"document": id, "chunks": n})),
+                warp::http::StatusCode::OK,
+            )
+        },
+    )
+}
+
+pub async fn delete_document(id: String, t: Tenant) -> impl Reply {
+    kb::delete(&id, &t).await.map_or_else(
+        |e| {
+            tracing::error!("{:#?}", e);
+            warp::reply::with_status(
+                warp::reply::json(&"An error occured on our side"),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        },
+        |n| {
+            warp::reply::with_status(
+                warp::reply::json(&json!({"document": id, "deleted": n})),
+                warp::http::StatusCode::OK,
+            )
+        },
+    )
+}
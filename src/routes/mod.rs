@@ -1,6 +1,24 @@
 pub mod add;
+pub mod analytics;
+pub mod backup;
+pub mod by_ip;
 pub mod chat;
+pub mod chat_export;
 pub mod chat_from;
 pub mod chats;
 pub mod chats_from;
-pub mod next_id;
\ No newline at end of file
+pub mod console;
+pub mod erase;
+pub mod faq;
+pub mod feed;
+pub mod health;
+pub mod ingest;
+pub mod kb;
+pub mod next_id;
+pub mod pseudonym;
+pub mod reply;
+pub mod retention;
+pub mod reply_stream;
+pub mod search;
+pub mod similar;
+pub mod visitor;
\ No newline at end of file
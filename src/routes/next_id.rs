@@ -5,7 +5,7 @@ use crate::util::id;
 pub async fn next_id() -> impl Reply {
     id().await.map_or_else(
         |e| {
-            log::error!("{:#?}", e);
+            tracing::error!("{:#?}", e);
             warp::reply::with_status(
                 "An error occured on our side".to_string(),
                 warp::http::StatusCode::INTERNAL_SERVER_ERROR,
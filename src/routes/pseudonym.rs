@@ -0,0 +1,10 @@
+use serde_json::json;
+use warp::reply::Reply;
+
+use crate::privacy::pseudonymize;
+
+/// Shows how a known address is stored, to find that visitor's messages.
+pub async fn pseudonym(address: String) -> impl Reply {
+    let stored = pseudonymize(&address).await;
+    warp::reply::json(&json!({"address": address, "stored_as": stored}))
+}
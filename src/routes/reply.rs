@@ -0,0 +1,29 @@
+use warp::reply::Reply;
+
+use crate::{
+    rag::{self, Question},
+    tenant::Tenant,
+    visitor::Visitor,
+};
+
+pub async fn reply(
+    id: String,
+    q: Question,
+    ip: Option<std::net::IpAddr>,
+    v: Visitor,
+    t: Tenant,
+) -> impl Reply {
+    let reply = rag::answer(id, q, ip.map(|a| a.to_string()), v.id.clone(), t)
+        .await
+        .map_or_else(
+            |e| {
+                tracing::error!("{:#?}", e);
+                warp::reply::with_status(
+                    warp::reply::json(&"An error occured on our side"),
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                )
+            },
+            |a| warp::reply::with_status(warp::reply::json(&a), warp::http::StatusCode::OK),
+        );
+    v.reply(reply)
+}
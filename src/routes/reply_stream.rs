@@ -0,0 +1,53 @@
+use std::convert::Infallible;
+
+use futures_util::{stream, StreamExt};
+use serde_json::json;
+use warp::{reply::Reply, sse::Event};
+
+use crate::{
+    rag::{self, Progress, Question},
+    tenant::Tenant,
+    visitor::Visitor,
+};
+
+/// Streams the answer as server-sent events: `citations` first, then one
+/// `token` per piece of the answer, then `done` with the stored question's ID,
+/// or `error` if the answer broke off.
+pub async fn reply_stream(
+    id: String,
+    q: Question,
+    ip: Option<std::net::IpAddr>,
+    v: Visitor,
+    t: Tenant,
+) -> warp::reply::Response {
+    match rag::stream(id, q, ip.map(|a| a.to_string()), v.id.clone(), t).await {
+        Ok((citations, progress)) => {
+            let first = Event::default()
+                .event("citations")
+                .json_data(&citations)
+                .unwrap_or_default();
+            let events = stream::once(async { first })
+                .chain(progress.map(|p| {
+                    match p {
+                        Progress::Token(t) => Event::default().event("token").data(t),
+                        Progress::Done(id) => Event::default()
+                            .event("done")
+                            .data(json!({ "id": id }).to_string()),
+                        Progress::Failed => Event::default()
+                            .event("error")
+                            .data("An error occured on our side"),
+                    }
+                }))
+                .map(Ok::<_, Infallible>);
+            v.reply(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+        }
+        Err(e) => {
+            tracing::error!("{:#?}", e);
+            warp::reply::with_status(
+                warp::reply::json(&"An error occured on our side"),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response()
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use serde::Deserialize;
+use warp::reply::Reply;
+
+use crate::retention::{policy, run};
+
+#[derive(Deserialize)]
+pub struct RetentionOptions {
+    /// Report what would be purged without changing anything.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+pub async fn retention(o: RetentionOptions) -> impl Reply {
+    let policy = policy().await;
+    run(&policy, o.dry_run || policy.dry_run).await.map_or_else(
+        |e| {
+            tracing::error!("{:#?}", e);
+            warp::reply::with_status(
+                warp::reply::json(&"An error occured on our side"),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        },
+        |r| warp::reply::with_status(warp::reply::json(&r), warp::http::StatusCode::OK),
+    )
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    search::{facets, grouped, recommend, semantic, Example},
+    tenant::Tenant,
+};
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String, // Query string
+    f: Option<HashMap<String, Value>>,
+}
+
+#[derive(Deserialize)]
+pub struct GroupSearch {
+    k: String,
+    q: String,
+    f: Option<HashMap<String, Value>>,
+}
+
+#[derive(Deserialize)]
+pub struct Recommend {
+    #[serde(default)]
+    positive: Vec<Example>,
+    #[serde(default)]
+    negative: Vec<Example>,
+    f: Option<HashMap<String, Value>>,
+    /// How many points to return, at most `search::MAX_RECOMMEND`.
+    #[serde(default = "default_limit")]
+    limit: usize,
+    strategy: Option<String>,
+}
+
+fn default_limit() -> usize {
+    7
+}
+
+#[derive(Deserialize)]
+pub struct Facets {
+    key: String,
+    /// The `/search` filter, JSON encoded.
+    f: Option<String>,
+    /// How many values to return, at most `search::MAX_FACETS`.
+    #[serde(default = "default_facet_limit")]
+    limit: usize,
+}
+
+fn default_facet_limit() -> usize {
+    20
+}
+
+pub async fn handle_search(q: SearchQuery, t: Tenant) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&semantic(&q.q, q.f.as_ref(), &t).await?))
+}
+
+pub async fn handle_group_search(
+    q: GroupSearch,
+    t: Tenant,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(
+        &grouped(&q.q, &q.k, q.f.as_ref(), &t).await?,
+    ))
+}
+
+pub async fn handle_recommend(
+    q: Recommend,
+    t: Tenant,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let hits = recommend(
+        &q.positive,
+        &q.negative,
+        q.f.as_ref(),
+        q.limit,
+        q.strategy.as_deref(),
+        &t,
+    )
+    .await?;
+    Ok(match hits {
+        Some(hits) => {
+            warp::reply::with_status(warp::reply::json(&hits), warp::http::StatusCode::OK)
+        }
+        None => warp::reply::with_status(
+            warp::reply::json(&"Unknown example point"),
+            warp::http::StatusCode::NOT_FOUND,
+        ),
+    })
+}
+
+pub async fn handle_facets(q: Facets, t: Tenant) -> Result<impl warp::Reply, warp::Rejection> {
+    let f = match q
+        .f
+        .as_deref()
+        .map(serde_json::from_str::<HashMap<String, Value>>)
+    {
+        None => None,
+        Some(Ok(f)) => Some(f),
+        Some(Err(_)) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&"`f` must be a JSON object"),
+                warp::http::StatusCode::BAD_REQUEST,
+            ))
+        }
+    };
+    Ok(match facets(&q.key, f.as_ref(), q.limit, &t).await? {
+        Some(facets) => {
+            warp::reply::with_status(warp::reply::json(&facets), warp::http::StatusCode::OK)
+        }
+        None => warp::reply::with_status(
+            warp::reply::json(&format!("`{}` is not a facet key", q.key)),
+            warp::http::StatusCode::BAD_REQUEST,
+        ),
+    })
+}
@@ -0,0 +1,34 @@
+use serde::Deserialize;
+use warp::reply::Reply;
+
+use crate::{conversation::similar, tenant::Tenant};
+
+#[derive(Deserialize)]
+pub struct SimilarOptions {
+    /// How many chats to return, at most `conversation::MAX_SIMILAR`.
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    5
+}
+
+pub async fn similar_chats(id: String, o: SimilarOptions, t: Tenant) -> impl Reply {
+    similar(&id, o.limit, &t).await.map_or_else(
+        |e| {
+            tracing::error!("{:#?}", e);
+            warp::reply::with_status(
+                warp::reply::json(&"An error occured on our side"),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        },
+        |s| match s {
+            Some(s) => warp::reply::with_status(warp::reply::json(&s), warp::http::StatusCode::OK),
+            None => warp::reply::with_status(
+                warp::reply::json(&"Not Found"),
+                warp::http::StatusCode::NOT_FOUND,
+            ),
+        },
+    )
+}
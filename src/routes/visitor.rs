@@ -0,0 +1,43 @@
+use serde::Deserialize;
+use warp::reply::Reply;
+
+use crate::visitor::{merge, summary};
+
+#[derive(Deserialize)]
+pub struct MergeVisitors {
+    from: String,
+    into: String,
+}
+
+pub async fn visitor_summary(id: String) -> impl Reply {
+    summary(&id).await.map_or_else(
+        |e| {
+            tracing::error!("{:#?}", e);
+            warp::reply::with_status(
+                warp::reply::json(&"An error occured on our side"),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        },
+        |s| {
+            let status = if s.chats.is_empty() {
+                warp::http::StatusCode::NOT_FOUND
+            } else {
+                warp::http::StatusCode::OK
+            };
+            warp::reply::with_status(warp::reply::json(&s), status)
+        },
+    )
+}
+
+pub async fn merge_visitors(m: MergeVisitors) -> impl Reply {
+    merge(&m.from, &m.into).await.map_or_else(
+        |e| {
+            tracing::error!("{:#?}", e);
+            warp::reply::with_status(
+                warp::reply::json(&"An error occured on our side"),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        },
+        |r| warp::reply::with_status(warp::reply::json(&r), warp::http::StatusCode::OK),
+    )
+}
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    app::{AppError, AppResult},
+    constants::{COLLECTION, SECRETS},
+    conversation::CONVERSATION_CATEGORY,
+    qdrant::{keyword_index, qdrant_path, qdrant_post},
+    telemetry::redact,
+    tenant::{Tenant, TENANT_FIELD},
+    util::embedding,
+};
+
+/// Payload keys `/facets` counts when `FACET_KEYS` isn't set.
+const DEFAULT_FACET_KEYS: &str = "c,p,g.country";
+
+/// Payload keys search hits carry: the text and role of a message, and the
+/// category, document ID, title, source URL and heading of a KB chunk.
+const HIT_PAYLOAD: &[&str] = &["m", "u", "c", "k", "t", "s", "h"];
+
+pub const MAX_RECOMMEND: usize = 50;
+pub const MAX_FACETS: usize = 100;
+
+/// Filter requiring every key of `f` to match its value exactly. Chat
+/// vectors are left out: they average a chat rather than hold any text.
+fn must(f: Option<&HashMap<String, Value>>) -> Value {
+    tracing::debug!(f = %redact(format!("{:?}", f)), "search filter");
+    let must: Vec<Value> = f
+        .into_iter()
+        .flatten()
+        .map(|(key, v)| json!({"key": key, "match": {"value": v}}))
+        .collect();
+    json!({
+        "must": must,
+        "must_not": [{"key": "c", "match": {"value": CONVERSATION_CATEGORY}}],
+    })
+}
+
+/// Nearest points to `vector`, best first.
+pub async fn search(
+    vector: Value,
+    filter: Option<Value>,
+    limit: usize,
+    with_payload: Value,
+) -> AppResult<Vec<Value>> {
+    let mut body = json!({"vector": vector, "limit": limit, "with_payload": with_payload});
+    if let Some(f) = filter {
+        body["filter"] = f;
+    }
+    Ok(qdrant_post(
+        &qdrant_path(&format!("collections/{}/points/search", COLLECTION)).await?,
+        body,
+    )
+    .await?["result"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default())
+}
+
+/// Messages closest in meaning to `q`, optionally narrowed to payloads
+/// matching `f`, within tenant `t`; what `/search` returns.
+pub async fn semantic(q: &str, f: Option<&HashMap<String, Value>>, t: &Tenant) -> AppResult<Value> {
+    let vector = embedding(q)
+        .await
+        .map_err(|e| AppError::new("q to string in handle_search", e))?;
+    Ok(json!(
+        search(vector, t.scope(Some(must(f))), 7, json!(HIT_PAYLOAD)).await?
+    ))
+}
+
+/// Like `semantic`, but returning the best hit per distinct value of payload
+/// key `k`; what `/search/group` returns.
+pub async fn grouped(
+    q: &str,
+    k: &str,
+    f: Option<&HashMap<String, Value>>,
+    t: &Tenant,
+) -> AppResult<Value> {
+    let mut body = json!({
+        "vector": embedding(q)
+            .await
+            .map_err(|e| AppError::new("q to string in handle_search", e))?,
+        "group_by": k,
+        "limit": 7,
+        "group_size": 1,
+        "with_payload": HIT_PAYLOAD,
+    });
+    if let Some(f) = t.scope(Some(must(f))) {
+        body["filter"] = f;
+    }
+    Ok(qdrant_post(
+        &qdrant_path(&format!("collections/{}/points/search/groups", COLLECTION)).await?,
+        body,
+    )
+    .await?["result"]
+        .clone())
+}
+
+/// Visitors grouped by IP, from their first message on or after `d`, within
+/// tenant `t`; what `/ip` returns.
+pub async fn visitors(d: i64, t: &Tenant) -> AppResult<Value> {
+    Ok(qdrant_post(
+        &qdrant_path(&format!("collections/{}/points/query/groups", COLLECTION)).await?,
+        json!({
+          "group_by": "a",
+          "limit": 7,
+          "group_size": 1,
+          "order_by": [{"key": "d", "order": "asc"}],
+          "with_payload": ["a"],
+          "filter": t.scope(Some(json!({
+            "must": [
+              {"key": "u", "match": {"value": 1}},
+              {"key": "d", "range": {"gte": d}}
+            ]
+          })))
+        }),
+    )
+    .await?["result"]
+        .clone())
+}
+
+/// A point to recommend from: one already stored, or text to embed.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Example {
+    Id { id: Value },
+    Text { text: String },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Hit {
+    pub id: Value,
+    pub score: f64,
+    pub role: &'static str,
+    pub text: String,
+    pub chat: Option<String>,
+    pub category: Option<String>,
+}
+
+impl Hit {
+    pub fn from_point(p: &Value) -> Self {
+        let string = |k: &str| p["payload"][k].as_str().map(str::to_string);
+        Hit {
+            id: p["id"].clone(),
+            score: p["score"].as_f64().unwrap_or_default(),
+            role: if p["payload"]["u"].as_i64() == Some(1) {
+                "user"
+            } else {
+                "assistant"
+            },
+            text: string("m").unwrap_or_default(),
+            chat: string("i"),
+            category: string("c"),
+        }
+    }
+}
+
+/// Stored points are passed by ID so Qdrant uses their vectors as they are;
+/// only text gets embedded.
+async fn examples(examples: &[Example]) -> AppResult<Vec<Value>> {
+    let mut out = Vec::with_capacity(examples.len());
+    for e in examples {
+        out.push(match e {
+            Example::Id { id } => id.clone(),
+            Example::Text { text } => embedding(text).await?,
+        });
+    }
+    Ok(out)
+}
+
+/// Whether every stored example exists and belongs to tenant `t`. Qdrant's
+/// filter only narrows the results, so without this a tenant could recommend
+/// from another's points, or probe which IDs exist.
+async fn owned(examples: &[&Example], t: &Tenant) -> AppResult<bool> {
+    let mut ids: Vec<&Value> = examples
+        .iter()
+        .filter_map(|e| match e {
+            Example::Id { id } => Some(id),
+            Example::Text { .. } => None,
+        })
+        .collect();
+    ids.sort_by_key(|id| id.to_string());
+    ids.dedup();
+    if ids.is_empty() {
+        return Ok(true);
+    }
+    let res = qdrant_post(
+        &qdrant_path(&format!("collections/{}/points", COLLECTION)).await?,
+        json!({"ids": ids, "with_payload": [TENANT_FIELD]}),
+    )
+    .await?;
+    let found = res["result"].as_array().cloned().unwrap_or_default();
+    Ok(found.len() == ids.len() && found.iter().all(|p| t.owns(&p["payload"])))
+}
+
+/// Points like `positive` and unlike `negative`, through Qdrant's recommend
+/// API, filtered and scoped like `semantic`. `strategy` is passed through,
+/// Qdrant's default being `average_vector`. `None` when an example ID isn't
+/// one of tenant `t`'s points.
+pub async fn recommend(
+    positive: &[Example],
+    negative: &[Example],
+    f: Option<&HashMap<String, Value>>,
+    limit: usize,
+    strategy: Option<&str>,
+    t: &Tenant,
+) -> AppResult<Option<Vec<Hit>>> {
+    if positive.is_empty() && negative.is_empty() {
+        return Err(AppError::new_plain("recommend needs at least one example"));
+    }
+    if !owned(&positive.iter().chain(negative).collect::<Vec<_>>(), t).await? {
+        return Ok(None);
+    }
+    let mut body = json!({
+        "positive": examples(positive).await?,
+        "negative": examples(negative).await?,
+        "limit": limit.clamp(1, MAX_RECOMMEND),
+        "with_payload": ["m", "u", "i", "c"],
+        "filter": t.scope(Some(must(f))),
+    });
+    if let Some(s) = strategy {
+        body["strategy"] = json!(s);
+    }
+    Ok(Some(
+        qdrant_post(
+            &qdrant_path(&format!("collections/{}/points/recommend", COLLECTION)).await?,
+            body,
+        )
+        .await?["result"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(Hit::from_point)
+            .collect(),
+    ))
+}
+
+/// Payload keys `/facets` may count: `FACET_KEYS`, comma separated. Each gets
+/// a keyword index on first use, so they should hold strings.
+pub async fn facet_keys() -> Vec<String> {
+    SECRETS
+        .lock()
+        .await
+        .get("FACET_KEYS")
+        .unwrap_or_else(|| DEFAULT_FACET_KEYS.to_string())
+        .split(',')
+        .map(|k| k.trim().to_string())
+        .filter(|k| !k.is_empty())
+        .collect()
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Facet {
+    pub value: Value,
+    pub count: u64,
+}
+
+/// Distinct values of payload `key` among tenant `t`'s points matching `f`,
+/// most common first, through Qdrant's facet API. Chat vectors aren't counted, so the
+/// categories of `c` are those of messages and KB chunks. `None` when `key` isn't one of
+/// `facet_keys`.
+pub async fn facets(
+    key: &str,
+    f: Option<&HashMap<String, Value>>,
+    limit: usize,
+    t: &Tenant,
+) -> AppResult<Option<Vec<Facet>>> {
+    if !facet_keys().await.iter().any(|k| k == key) {
+        return Ok(None);
+    }
+    keyword_index(COLLECTION, key).await?;
+    let body = json!({
+        "key": key,
+        "limit": limit.clamp(1, MAX_FACETS),
+        "exact": true,
+        "filter": t.scope(Some(must(f))),
+    });
+    let mut facets: Vec<Facet> = qdrant_post(
+        &qdrant_path(&format!("collections/{}/facet", COLLECTION)).await?,
+        body,
+    )
+    .await?["result"]["hits"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|h| Facet {
+            value: h["value"].clone(),
+            count: h["count"].as_u64().unwrap_or_default(),
+        })
+        .collect();
+    facets.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.value.to_string().cmp(&b.value.to_string()))
+    });
+    Ok(Some(facets))
+}
@@ -0,0 +1,244 @@
+use std::collections::HashSet;
+
+use serde_json::{json, Value};
+
+use crate::{
+    app::{AppError, AppResult},
+    bus::{self, Event},
+    constants::COLLECTION,
+    conversation, geo, privacy,
+    qdrant::{qdrant_path, qdrant_post, qdrant_put},
+    tenant::{Tenant, TENANT_FIELD},
+    util::{embeddings, id},
+    visitor,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Category of chat messages written by this service.
+pub const MESSAGE_CATEGORY: &str = "m";
+
+/// A chat message about to be written: one point in the collection, embedded
+/// from its text.
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub id: Value,
+    /// Chat ID, stored as `i`.
+    pub chat: String,
+    /// Stored as `u`: 1 for the visitor, 0 for the assistant.
+    pub user: bool,
+    /// Stored as `m`.
+    pub text: String,
+    /// Page the chat happened on, stored as `p`.
+    pub page: Option<String>,
+    /// Client timestamp, stored as `d`.
+    pub date: Value,
+    /// Visitor address, stored as `a` after `privacy` pseudonymizes it. On
+    /// visitor messages it is also located and the result stored as `g`.
+    pub ip: Option<String>,
+    /// ID of the record this message was imported from, stored as `x`.
+    pub external_id: Option<String>,
+    /// Visitor ID, stored as `v` after following merges.
+    pub visitor: Option<String>,
+    /// Tenant the message belongs to, stored as `o`.
+    pub tenant: Option<String>,
+    /// An answer cut short, e.g. by the visitor leaving mid-stream; stored as
+    /// `z` = 1.
+    pub incomplete: bool,
+}
+
+impl Message {
+    pub fn payload(&self) -> Value {
+        let mut payload = json!({
+            "u": if self.user { 1 } else { 0 },
+            "m": self.text,
+            "c": MESSAGE_CATEGORY,
+            "i": self.chat,
+            "d": self.date,
+        });
+        if let Some(p) = &self.page {
+            payload["p"] = json!(p);
+        }
+        if let Some(a) = &self.ip {
+            payload["a"] = json!(a);
+        }
+        if let Some(x) = &self.external_id {
+            payload["x"] = json!(x);
+        }
+        if let Some(v) = &self.visitor {
+            payload["v"] = json!(v);
+        }
+        if let Some(o) = &self.tenant {
+            payload[TENANT_FIELD] = json!(o);
+        }
+        if self.incomplete {
+            payload["z"] = json!(1);
+        }
+        payload
+    }
+}
+
+/// Point ID for tenant `t`'s imported record, derived from its external ID so
+/// importing the same record twice overwrites rather than duplicates it.
+/// External IDs are per tenant.
+pub fn external_point_id(external_id: &str, t: &Tenant) -> Value {
+    let name = match &t.0 {
+        Some(tenant) => format!("qdrant-warp:ingest:{}:{}", tenant, external_id),
+        None => format!("qdrant-warp:ingest:{}", external_id),
+    };
+    json!(uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_URL, name.as_bytes()).to_string())
+}
+
+/// Embeds `messages` in one batch and upserts them in one request, waiting for
+/// Qdrant to apply the write, then folds the new ones into their chats'
+/// vectors and announces each live one on the bus. Imported messages, those
+/// with an external ID, are history and not announced.
+pub async fn save(messages: &[Message]) -> AppResult<()> {
+    if messages.is_empty() {
+        return Ok(());
+    }
+    let texts: Vec<String> = messages.iter().map(|m| m.text.clone()).collect();
+    let vectors = embeddings(&texts).await?;
+    if vectors.iter().any(Value::is_null) {
+        return Err(AppError::new_plain("embedding service returned no vector"));
+    }
+    let mut messages = messages.to_vec();
+    for m in &mut messages {
+        if let Some(v) = &m.visitor {
+            m.visitor = Some(visitor::resolve(v).await?);
+        }
+    }
+    let privacy = privacy::config().await;
+    let points: Vec<Value> = messages
+        .iter()
+        .zip(&vectors)
+        .map(|(m, v)| {
+            let mut payload = m.payload();
+            if let Some(a) = &m.ip {
+                // located before pseudonymizing, which can hide the address
+                if let Some(g) = a.parse().ok().filter(|_| m.user).and_then(geo::locate) {
+                    payload["g"] = json!(g);
+                }
+                payload["a"] = json!(privacy.pseudonymize(a));
+            }
+            json!({"id": m.id, "payload": payload, "vector": v})
+        })
+        .collect();
+    // overwritten points, such as a re-imported record, are already in
+    // their chat's vector
+    let existing: HashSet<String> = qdrant_post(
+        &qdrant_path(&format!("collections/{}/points", COLLECTION)).await?,
+        json!({
+            "ids": messages.iter().map(|m| &m.id).collect::<Vec<_>>(),
+            "with_payload": false,
+        }),
+    )
+    .await?["result"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|p| p["id"].to_string())
+        .collect();
+    qdrant_put(
+        &qdrant_path(&format!("collections/{}/points?wait=true", COLLECTION)).await?,
+        json!({ "points": points }),
+    )
+    .await?;
+    let added: Vec<(String, Option<String>, Value)> = messages
+        .iter()
+        .zip(vectors)
+        .filter(|(m, _)| !existing.contains(&m.id.to_string()))
+        .map(|(m, v)| (m.chat.clone(), m.tenant.clone(), v))
+        .collect();
+    // the messages are stored either way; the chat vector catches up later
+    if let Err(e) = conversation::absorb(&added).await {
+        tracing::error!("{:#?}", e);
+    }
+    for m in messages.into_iter().filter(|m| m.external_id.is_none()) {
+        bus::publish(Event::Message {
+            id: m.id.clone(),
+            chat: m.chat.clone(),
+            visitor: m.visitor.clone(),
+            tenant: m.tenant.clone(),
+            role: if m.user { "user" } else { "assistant" },
+            text: m.text.clone(),
+            page: m.page.clone(),
+            date: m.date.clone(),
+            incomplete: m.incomplete,
+        });
+    }
+    Ok(())
+}
+
+/// One visitor question and the assistant's answer.
+pub struct Turn {
+    pub chat: String,
+    pub page: Option<String>,
+    pub ip: Option<String>,
+    pub visitor: Option<String>,
+    pub tenant: Option<String>,
+    pub question: String,
+    pub question_date: Value,
+    pub answer: String,
+    pub answer_date: Value,
+    /// The answer stopped before the LLM finished it.
+    pub incomplete: bool,
+}
+
+/// Writes a turn, taking the question's ID from `util::id` and returning it.
+pub async fn save_turn(t: Turn) -> AppResult<String> {
+    let id = id().await?;
+    save(&turn_messages(&id, t)).await?;
+    Ok(id)
+}
+
+/// The points a turn is stored as. The question gets `id`; the answer gets its
+/// own time-ordered UUID, as a point written under the question's ID would
+/// replace the question, which is what `/` did when it stored both under one
+/// ID. An incomplete turn with no answer text at all is only its question.
+fn turn_messages(id: &str, t: Turn) -> Vec<Message> {
+    let question = Message {
+        id: id.parse::<u64>().map_or_else(|_| json!(id), |n| json!(n)),
+        chat: t.chat,
+        user: true,
+        text: t.question,
+        page: t.page,
+        date: t.question_date,
+        ip: t.ip,
+        visitor: t.visitor,
+        tenant: t.tenant,
+        external_id: None,
+        incomplete: false,
+    };
+    let answer = Message {
+        id: json!(uuid::Uuid::now_v7().to_string()),
+        user: false,
+        text: t.answer,
+        date: t.answer_date,
+        ip: None,
+        incomplete: t.incomplete,
+        ..question.clone()
+    };
+    if t.incomplete && answer.text.trim().is_empty() {
+        vec![question]
+    } else {
+        vec![question, answer]
+    }
+}
+
+/// Milliseconds since the epoch, the `d` of messages the service writes itself.
+pub fn now() -> Value {
+    json!(chrono::Utc::now().timestamp_millis())
+}
+
+/// A client-sent date as stored in `d`. Numeric strings become numbers and
+/// RFC 3339 strings milliseconds, so range filters, such as retention's and
+/// `/ip`'s, can match them.
+pub fn date(d: String) -> Value {
+    if let Ok(n) = d.trim().parse::<i64>() {
+        return json!(n);
+    }
+    chrono::DateTime::parse_from_rfc3339(d.trim())
+        .map_or_else(|_| json!(d), |t| json!(t.timestamp_millis()))
+}
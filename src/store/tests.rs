@@ -0,0 +1,71 @@
+use serde_json::json;
+
+use super::{date, turn_messages, Turn};
+
+fn turn(answer: &str, incomplete: bool) -> Turn {
+    Turn {
+        chat: "chat-1".to_string(),
+        page: Some("/pricing".to_string()),
+        ip: Some("203.0.113.7".to_string()),
+        visitor: Some("visitor-1".to_string()),
+        tenant: Some("t1".to_string()),
+        question: "How much is it?".to_string(),
+        question_date: json!(1_700_000_000_000_i64),
+        answer: answer.to_string(),
+        answer_date: json!(1_700_000_001_000_i64),
+        incomplete,
+    }
+}
+
+#[test]
+fn answer_does_not_reuse_the_question_id() {
+    let m = turn_messages("41", turn("Ten euros.", false));
+    assert_eq!(m.len(), 2);
+    assert_eq!(m[0].id, json!(41));
+    assert!(m[0].user);
+    assert!(!m[1].user);
+    assert_ne!(m[1].id, m[0].id);
+    assert!(m[1]
+        .id
+        .as_str()
+        .is_some_and(|u| uuid::Uuid::parse_str(u).is_ok()));
+}
+
+#[test]
+fn answer_shares_the_turn_but_not_the_address() {
+    let m = turn_messages("41", turn("Ten euros.", false));
+    assert_eq!(m[1].chat, "chat-1");
+    assert_eq!(m[1].page.as_deref(), Some("/pricing"));
+    assert_eq!(m[1].visitor.as_deref(), Some("visitor-1"));
+    assert_eq!(m[1].tenant.as_deref(), Some("t1"));
+    assert_eq!(m[0].ip.as_deref(), Some("203.0.113.7"));
+    assert_eq!(m[1].ip, None);
+}
+
+#[test]
+fn incomplete_turn_without_answer_is_the_question_alone() {
+    let m = turn_messages("41", turn("  ", true));
+    assert_eq!(m.len(), 1);
+    assert!(m[0].user);
+
+    let m = turn_messages("41", turn("Ten", true));
+    assert_eq!(m.len(), 2);
+    assert!(m[1].incomplete);
+    assert!(!m[0].incomplete);
+}
+
+#[test]
+fn non_numeric_ids_stay_strings() {
+    let m = turn_messages("abc", turn("Ten euros.", false));
+    assert_eq!(m[0].id, json!("abc"));
+}
+
+#[test]
+fn client_dates_become_numbers() {
+    assert_eq!(date(" 1700000000 ".into()), json!(1_700_000_000));
+    assert_eq!(
+        date("2023-11-14T22:13:20+00:00".into()),
+        json!(1_700_000_000_000_i64)
+    );
+    assert_eq!(date("yesterday".into()), json!("yesterday"));
+}
@@ -0,0 +1,120 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing::{info_span, Span};
+use tracing_subscriber::{
+    fmt::format::FmtSpan, layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt,
+    EnvFilter, Registry,
+};
+
+use crate::{client_ip, constants::SECRETS};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest client-supplied request ID that is kept.
+const MAX_REQUEST_ID: usize = 128;
+
+static REDACT: AtomicBool = AtomicBool::new(true);
+
+/// Request ID stored on the request span so it can be echoed back in the
+/// response.
+struct RequestId(String);
+
+/// Installs the global subscriber. `LOG_FORMAT` selects `json` or `pretty`
+/// output and `LOG_REDACT=false` lets message bodies and IPs through to the
+/// logs; the filter itself comes from `RUST_LOG`.
+pub async fn init() {
+    let secrets = SECRETS.lock().await;
+    REDACT.store(
+        secrets.get("LOG_REDACT").as_deref() != Some("false"),
+        Ordering::Relaxed,
+    );
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let registry = tracing_subscriber::registry().with(filter);
+    let res = match secrets.get("LOG_FORMAT").as_deref() {
+        Some("json") => registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_events(FmtSpan::CLOSE),
+            )
+            .try_init(),
+        _ => registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .pretty()
+                    .with_span_events(FmtSpan::CLOSE),
+            )
+            .try_init(),
+    };
+    if let Err(e) = res {
+        eprintln!("tracing subscriber already installed: {}", e);
+    }
+}
+
+/// Replaces `v` with a placeholder unless redaction has been turned off.
+pub fn redact(v: impl std::fmt::Display) -> String {
+    if REDACT.load(Ordering::Relaxed) {
+        "[redacted]".to_string()
+    } else {
+        v.to_string()
+    }
+}
+
+/// Whether a client-supplied request ID is safe to log and echo back: at most
+/// `MAX_REQUEST_ID` bytes of letters, digits, `.`, `_` and `-`.
+fn valid_request_id(v: &str) -> bool {
+    !v.is_empty()
+        && v.len() <= MAX_REQUEST_ID
+        && v.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+/// Builds the span every request runs in, taking the request ID from the
+/// `X-Request-Id` header when it is valid or generating one.
+pub fn request_span(info: warp::trace::Info) -> Span {
+    let id = info
+        .request_headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| valid_request_id(v))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::now_v7().to_string());
+    let client = client_ip::resolve(info.remote_addr(), info.request_headers())
+        .map(redact)
+        .unwrap_or_default();
+    let span = info_span!(
+        "request",
+        request_id = %id,
+        method = %info.method(),
+        path = %info.path(),
+        client = %client,
+    );
+    span.with_subscriber(|(sid, dispatch)| {
+        if let Some(s) = dispatch
+            .downcast_ref::<Registry>()
+            .and_then(|r| r.span(sid))
+        {
+            s.extensions_mut().insert(RequestId(id));
+        }
+    });
+    span
+}
+
+/// The ID of the request currently being handled, if any.
+pub fn request_id() -> Option<String> {
+    Span::current()
+        .with_subscriber(|(sid, dispatch)| {
+            dispatch
+                .downcast_ref::<Registry>()?
+                .span(sid)?
+                .extensions()
+                .get::<RequestId>()
+                .map(|r| r.0.clone())
+        })
+        .flatten()
+}
+
+pub fn with_request_id(reply: impl warp::Reply) -> impl warp::Reply {
+    warp::reply::with_header(reply, REQUEST_ID_HEADER, request_id().unwrap_or_default())
+}
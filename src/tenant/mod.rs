@@ -0,0 +1,107 @@
+//! Tenants: the client sites sharing the collection. `TENANT_KEYS` maps API
+//! keys to tenants as `key:tenant` pairs, comma separated; requests name
+//! theirs with an `X-Api-Key` header. Everything written for a tenant, chat
+//! messages and answers, items, chat vectors and knowledge-base chunks, is
+//! stamped with it in `o`, and every public read only sees its tenant's
+//! points, whatever filter the client sends. Admin views span all tenants;
+//! knowledge-base documents and ingested messages an admin writes go to the
+//! tenant of the API key sent along.
+//!
+//! Without `TENANT_KEYS` the service stays single-tenant: nothing is stamped
+//! or filtered and no key is asked for.
+
+use serde_json::{json, Value};
+use warp::{Filter, Rejection};
+
+use crate::{
+    auth::{same, Unauthorized},
+    constants::{COLLECTION, SECRETS},
+    qdrant::{qdrant_path, qdrant_put},
+};
+
+pub const TENANT_HEADER: &str = "x-api-key";
+/// Payload key holding the tenant.
+pub const TENANT_FIELD: &str = "o";
+
+/// The tenant a request acts for; `None` when tenancy is off, or for admin
+/// views that span every tenant.
+#[derive(Clone, Debug)]
+pub struct Tenant(pub Option<String>);
+
+impl Tenant {
+    /// Every tenant's points, for admin use.
+    pub const ALL: Tenant = Tenant(None);
+
+    /// `filter` narrowed to this tenant. The client's filter is nested as a
+    /// whole, so nothing in it can widen the result.
+    pub fn scope(&self, filter: Option<Value>) -> Option<Value> {
+        let Some(t) = &self.0 else {
+            return filter;
+        };
+        let mut must = vec![json!({"key": TENANT_FIELD, "match": {"value": t}})];
+        must.extend(filter);
+        Some(json!({ "must": must }))
+    }
+
+    /// Whether a point with `payload` is this tenant's to see.
+    pub fn owns(&self, payload: &Value) -> bool {
+        self.0
+            .as_deref()
+            .is_none_or(|t| payload[TENANT_FIELD].as_str() == Some(t))
+    }
+}
+
+/// The `TENANT_KEYS` pairs, `None` when tenancy is off.
+async fn keys() -> Option<Vec<(String, String)>> {
+    let spec = SECRETS.lock().await.get("TENANT_KEYS")?;
+    Some(
+        spec.split(',')
+            .filter_map(|pair| {
+                let (key, tenant) = pair.split_once(':')?;
+                let (key, tenant) = (key.trim(), tenant.trim());
+                (!key.is_empty() && !tenant.is_empty())
+                    .then(|| (key.to_string(), tenant.to_string()))
+            })
+            .collect(),
+    )
+}
+
+async fn identify(key: Option<String>) -> Result<Tenant, Rejection> {
+    let Some(keys) = keys().await else {
+        return Ok(Tenant::ALL);
+    };
+    let key = key.unwrap_or_default();
+    keys.into_iter()
+        .find(|(k, _)| same(k.as_bytes(), key.trim().as_bytes()))
+        .map(|(_, tenant)| Tenant(Some(tenant)))
+        .ok_or_else(|| warp::reject::custom(Unauthorized))
+}
+
+/// Extracts the request's tenant from its API key, rejecting a missing or
+/// unknown key while tenancy is on.
+pub fn tenant() -> impl Filter<Extract = (Tenant,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(TENANT_HEADER).and_then(identify)
+}
+
+/// Creates the tenant index when tenancy is on. Marked `is_tenant`, it lets
+/// Qdrant lay points out by tenant so scoped searches only touch theirs.
+pub async fn init() {
+    if keys().await.is_none() {
+        return;
+    }
+    let res = async {
+        qdrant_put(
+            &qdrant_path(&format!("collections/{}/index?wait=true", COLLECTION)).await?,
+            json!({
+                "field_name": TENANT_FIELD,
+                "field_schema": {"type": "keyword", "is_tenant": true},
+            }),
+        )
+        .await
+    }
+    .await;
+    match res {
+        Ok(_) => tracing::info!("tenant index ready"),
+        Err(e) => tracing::error!("{:#?}", e),
+    }
+}
@@ -0,0 +1,320 @@
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    app::AppResult,
+    constants::{COLLECTION, SITE_CHAT_MESSAGE_CATEGORY},
+    qdrant::{qdrant_path, qdrant_post},
+    tenant::Tenant,
+};
+
+/// Payload keys with a dedicated field on `Message`; the rest is metadata.
+const KNOWN_KEYS: &[&str] = &["u", "m", "d", "p", "c", "i"];
+/// Payload keys that identify the visitor, pseudonymized IP and visitor ID,
+/// which transcripts never carry.
+const PRIVATE_KEYS: &[&str] = &["a", "v"];
+const PAGE: usize = 256;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Message {
+    pub id: Value,
+    pub role: &'static str,
+    pub text: String,
+    /// `d` exactly as stored.
+    pub date: Value,
+    /// `d` read as seconds or milliseconds since the epoch, when it is one.
+    pub time: Option<DateTime<Utc>>,
+    pub page: Option<String>,
+    pub category: Option<String>,
+    pub metadata: Map<String, Value>,
+}
+
+/// `d` values above this are milliseconds, below it seconds.
+const MILLIS_FROM: i64 = 100_000_000_000;
+
+/// Reads a `d` payload value as a point in time. Clients send either seconds or
+/// milliseconds since the epoch, as a number or a numeric string, or an RFC 3339
+/// string such as `2024-05-01T12:00:00Z`.
+pub fn timestamp(d: &Value) -> Option<DateTime<Utc>> {
+    let n = match d {
+        Value::Number(n) => n.as_i64()?,
+        Value::String(s) => match s.trim().parse() {
+            Ok(n) => n,
+            Err(_) => {
+                return DateTime::parse_from_rfc3339(s.trim())
+                    .ok()
+                    .map(|t| t.with_timezone(&Utc))
+            }
+        },
+        _ => return None,
+    };
+    if n > MILLIS_FROM {
+        Utc.timestamp_millis_opt(n).single()
+    } else {
+        Utc.timestamp_opt(n, 0).single()
+    }
+}
+
+/// Filter condition on `d` falling in `[from, to)`, matching both second and
+/// millisecond values. Only numeric `d` values can match.
+pub fn date_range(from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> Value {
+    let mut millis = json!({"gte": MILLIS_FROM});
+    let mut seconds = json!({"lt": MILLIS_FROM});
+    if let Some(from) = from {
+        millis["gte"] = json!(from.timestamp_millis().max(MILLIS_FROM));
+        seconds["gte"] = json!(from.timestamp());
+    }
+    if let Some(to) = to {
+        millis["lt"] = json!(to.timestamp_millis());
+        seconds["lt"] = json!(to.timestamp().min(MILLIS_FROM));
+    }
+    json!({"should": [
+        {"key": "d", "range": millis},
+        {"key": "d", "range": seconds},
+    ]})
+}
+
+impl Message {
+    pub fn from_point(p: &Value) -> Self {
+        let payload = p["payload"].as_object().cloned().unwrap_or_default();
+        let string = |k: &str| payload.get(k).and_then(Value::as_str).map(str::to_string);
+        Message {
+            id: p["id"].clone(),
+            role: if payload.get("u").and_then(Value::as_i64) == Some(1) {
+                "user"
+            } else {
+                "assistant"
+            },
+            text: string("m").unwrap_or_default(),
+            date: payload.get("d").cloned().unwrap_or(Value::Null),
+            time: payload.get("d").and_then(timestamp),
+            page: string("p"),
+            category: string("c"),
+            metadata: payload
+                .into_iter()
+                .filter(|(k, _)| {
+                    !KNOWN_KEYS.contains(&k.as_str()) && !PRIVATE_KEYS.contains(&k.as_str())
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Every message of chat `id` within tenant `t`, oldest first, users before
+/// assistants within a turn.
+pub async fn load(id: &str, t: &Tenant) -> AppResult<Vec<Message>> {
+    let mut messages = vec![];
+    let mut offset = Value::Null;
+    loop {
+        let mut body = json!({
+            "limit": PAGE,
+            "with_payload": true,
+            "filter": t.scope(Some(json!({"must": [
+                {"key": "i", "match": {"value": id}},
+                {"key": "c", "match": {"any": ["m", SITE_CHAT_MESSAGE_CATEGORY]}},
+            ]}))),
+        });
+        if !offset.is_null() {
+            body["offset"] = offset;
+        }
+        let res = qdrant_post(
+            &qdrant_path(&format!("collections/{}/points/scroll", COLLECTION)).await?,
+            body,
+        )
+        .await?;
+        messages.extend(
+            res["result"]["points"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(Message::from_point),
+        );
+        offset = res["result"]["next_page_offset"].clone();
+        if offset.is_null() {
+            break;
+        }
+    }
+    messages.sort_by(|a, b| {
+        a.time
+            .cmp(&b.time)
+            .then_with(|| a.date.to_string().cmp(&b.date.to_string()))
+            .then_with(|| (a.role != "user").cmp(&(b.role != "user")))
+    });
+    Ok(messages)
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Md,
+    Jsonl,
+    Csv,
+    Html,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Md => "text/markdown; charset=utf-8",
+            Format::Jsonl => "application/x-ndjson",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Md => "md",
+            Format::Jsonl => "jsonl",
+            Format::Csv => "csv",
+            Format::Html => "html",
+        }
+    }
+
+    pub fn render(self, id: &str, messages: &[Message]) -> String {
+        match self {
+            Format::Md => markdown(id, messages),
+            Format::Jsonl => jsonl(messages),
+            Format::Csv => csv(messages),
+            Format::Html => html(id, messages),
+        }
+    }
+}
+
+fn when(m: &Message) -> String {
+    match (&m.time, &m.date) {
+        (Some(t), _) => t.to_rfc3339(),
+        (None, Value::String(s)) => s.clone(),
+        (None, Value::Null) => String::new(),
+        (None, d) => d.to_string(),
+    }
+}
+
+/// Backslash-escapes what Markdown would read as emphasis, code, links or
+/// inline HTML, and a leading heading or list marker, so text renders as
+/// written.
+fn escape_markdown(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for (i, c) in s.chars().enumerate() {
+        if "\\`*_[]<>|~&".contains(c) || (i == 0 && "#+-=".contains(c)) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn markdown(id: &str, messages: &[Message]) -> String {
+    let mut out = format!("# Chat {}\n", escape_markdown(id));
+    for m in messages {
+        out.push_str(&format!("\n**{}** · {}", m.role, escape_markdown(&when(m))));
+        if let Some(p) = &m.page {
+            out.push_str(&format!(" · {}", escape_markdown(p)));
+        }
+        out.push_str("\n\n");
+        for line in m.text.lines() {
+            out.push_str(&format!("> {}\n", escape_markdown(line)));
+        }
+        if !m.metadata.is_empty() {
+            out.push_str(&format!(
+                "\n<sub>{}</sub>\n",
+                escape_html(&Value::Object(m.metadata.clone()).to_string())
+            ));
+        }
+    }
+    out
+}
+
+fn jsonl(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .filter_map(|m| serde_json::to_string(m).ok())
+        .map(|l| l + "\n")
+        .collect()
+}
+
+/// Quotes a CSV cell, and defuses one a spreadsheet would read as a formula
+/// by prefixing `'`. Leading tabs and carriage returns are defused too, as
+/// some spreadsheets skip them before looking for a formula.
+fn csv_field(s: &str) -> String {
+    let s = if s.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", s)
+    } else {
+        s.to_string()
+    };
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s
+    }
+}
+
+fn csv(messages: &[Message]) -> String {
+    let mut out = "id,role,time,page,category,text,metadata\n".to_string();
+    for m in messages {
+        let id = match &m.id {
+            Value::String(s) => s.clone(),
+            v => v.to_string(),
+        };
+        let row = [
+            id,
+            m.role.to_string(),
+            when(m),
+            m.page.clone().unwrap_or_default(),
+            m.category.clone().unwrap_or_default(),
+            m.text.clone(),
+            if m.metadata.is_empty() {
+                String::new()
+            } else {
+                Value::Object(m.metadata.clone()).to_string()
+            },
+        ];
+        out.push_str(
+            &row.iter()
+                .map(|f| csv_field(f))
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+        out.push('\n');
+    }
+    out
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn html(id: &str, messages: &[Message]) -> String {
+    let mut out = format!(
+        "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>Chat {0}</title>\
+         <style>body{{font-family:sans-serif;max-width:48em;margin:auto}}\
+         .user{{background:#eef}}.assistant{{background:#efe}}\
+         div{{padding:.5em;margin:.5em 0;border-radius:4px}}\
+         small{{color:#666}}</style></head><body><h1>Chat {0}</h1>\n",
+        escape_html(id)
+    );
+    for m in messages {
+        out.push_str(&format!(
+            "<div class=\"{}\"><small>{} · {}{}</small><p>{}</p></div>\n",
+            m.role,
+            m.role,
+            escape_html(&when(m)),
+            m.page
+                .as_ref()
+                .map(|p| format!(" · {}", escape_html(p)))
+                .unwrap_or_default(),
+            escape_html(&m.text).replace('\n', "<br>"),
+        ));
+    }
+    out.push_str("</body></html>\n");
+    out
+}
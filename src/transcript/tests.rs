@@ -0,0 +1,82 @@
+use serde_json::{json, Value};
+
+use super::{csv_field, timestamp, Format, Message};
+
+fn message(payload: Value) -> Message {
+    Message::from_point(&json!({"id": 7, "payload": payload}))
+}
+
+#[test]
+fn markdown_escapes_text_and_page() {
+    let m = message(json!({
+        "u": 1,
+        "m": "<img src=x onerror=alert(1)> [click](https://evil.example) *now*\n# not a heading",
+        "d": 1_700_000_000,
+        "p": "/pricing`<b>",
+    }));
+    let out = Format::Md.render("chat-1", &[m]);
+    assert!(out.contains(
+        "> \\<img src=x onerror=alert(1)\\> \\[click\\](https://evil.example) \\*now\\*\n"
+    ));
+    assert!(out.contains("> \\# not a heading\n"));
+    assert!(out.contains(" · /pricing\\`\\<b\\>\n"));
+    assert!(!out.replace("\\<", "").contains('<'));
+}
+
+#[test]
+fn markdown_escapes_metadata_html() {
+    let m = message(json!({"u": 0, "m": "hi", "note": "</sub><script>"}));
+    let out = Format::Md.render("chat-1", &[m]);
+    assert!(!out.contains("<script>"));
+    assert!(out.contains("&lt;/sub&gt;&lt;script&gt;"));
+}
+
+#[test]
+fn csv_defuses_formulas() {
+    for cell in ["=1+1", "+1", "-1", "@SUM(A1)", "\tx", "\rx"] {
+        assert!(
+            csv_field(cell).trim_start_matches('"').starts_with('\''),
+            "{:?}",
+            cell
+        );
+    }
+    assert_eq!(csv_field("plain"), "plain");
+    assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+}
+
+#[test]
+fn csv_rows_quote_and_defuse_text() {
+    let m = message(json!({"u": 1, "m": "=HYPERLINK(\"x\"), then", "d": 1_700_000_000}));
+    let out = Format::Csv.render("chat-1", &[m]);
+    let row = out.lines().nth(1).unwrap();
+    assert!(row.starts_with("7,user,2023-11-14T22:13:20+00:00,,,"));
+    assert!(row.contains(",\"'=HYPERLINK(\"\"x\"\"), then\","));
+}
+
+#[test]
+fn html_escapes_everything_from_the_chat() {
+    let m = message(json!({"u": 1, "m": "<script>alert(1)</script>\nbye", "p": "\"><b>"}));
+    let out = Format::Html.render("<i>", &[m]);
+    assert!(!out.contains("<script>"));
+    assert!(!out.contains("<i>"));
+    assert!(out.contains("&lt;script&gt;alert(1)&lt;/script&gt;<br>bye"));
+    assert!(out.contains(" · &quot;&gt;&lt;b&gt;"));
+}
+
+#[test]
+fn exports_leave_out_visitor_keys() {
+    let m = message(json!({"u": 1, "m": "hi", "a": "3f2a", "v": "visitor-1", "lang": "nl"}));
+    let out = Format::Jsonl.render("chat-1", &[m]);
+    assert!(!out.contains("3f2a"));
+    assert!(!out.contains("visitor-1"));
+    assert!(out.contains("\"lang\":\"nl\""));
+}
+
+#[test]
+fn timestamps_read_seconds_millis_and_rfc3339() {
+    let t = timestamp(&json!(1_700_000_000)).unwrap();
+    assert_eq!(timestamp(&json!(1_700_000_000_000_i64)), Some(t));
+    assert_eq!(timestamp(&json!("1700000000")), Some(t));
+    assert_eq!(timestamp(&json!("2023-11-15T00:13:20+02:00")), Some(t));
+    assert_eq!(timestamp(&json!("2023-11-14")), None);
+}
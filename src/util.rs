@@ -1,6 +1,15 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use futures_util::{stream, Stream, StreamExt};
+use once_cell::sync::Lazy;
 use serde_json::json;
+use tokio::sync::Mutex;
+use tracing::Instrument;
 use crate::app::AppError;
-use crate::constants::{I_ID, SECRETS};
+use crate::constants::{EMBEDDING_CACHE_SIZE, EMBEDDING_RETRIES, EMBEDDING_TIMEOUT, I_ID, SECRETS};
+use crate::metrics::{EMBEDDING_BATCH_SIZE, EMBEDDING_CACHE, EMBEDDING_LATENCY, ID_ALLOCATIONS};
+use crate::resilience::{call, Failure, Policy, EMBEDDING_BREAKER};
 
 use crate::{app::AppResult, qdrant::{qdrant_path, qdrant_post}};
 
@@ -22,34 +31,200 @@ pub async fn id() -> AppResult<String> {
         json!({"payload": {"sc": next}, "points": [I_ID]}),
     )
     .await?;
-    println!(
-        "new_id: {}",
-        qdrant_post(
-            &qdrant_path("collections/i/points").await?,
-            json!({"ids": [I_ID], "with_payload": ["sc"]}),
-        )
-        .await?
-    );
-    println!("id, next: {}, {}", id, next);
+    tracing::debug!(id, next, "allocated id");
+    ID_ALLOCATIONS.inc();
     Ok(id.to_string())
 }
 
+const EMBEDDING_POLICY: Policy = Policy {
+    timeout: EMBEDDING_TIMEOUT,
+    retries: EMBEDDING_RETRIES,
+    idempotent: true,
+};
+
+static EMBEDDINGS: Lazy<Mutex<HashMap<String, serde_json::Value>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Empties the embedding cache, whose keys are message texts, after points
+/// were erased or purged.
+pub async fn forget_embeddings() {
+    EMBEDDINGS.lock().await.clear();
+}
+
 pub async fn embedding(query: &str) -> AppResult<serde_json::Value> {
+    if let Some(v) = EMBEDDINGS.lock().await.get(query) {
+        EMBEDDING_CACHE.with_label_values(&["hit"]).inc();
+        return Ok(v.clone());
+    }
+    EMBEDDING_CACHE.with_label_values(&["miss"]).inc();
+    let v = fetch_embedding(query).await?;
+    let mut cache = EMBEDDINGS.lock().await;
+    if cache.len() >= EMBEDDING_CACHE_SIZE {
+        cache.clear();
+    }
+    cache.insert(query.to_string(), v.clone());
+    Ok(v)
+}
+
+/// Embeds several inputs, serving what it can from the cache and sending the
+/// rest to the embedding service in a single request.
+pub async fn embeddings(inputs: &[String]) -> AppResult<Vec<serde_json::Value>> {
+    let mut out = vec![serde_json::Value::Null; inputs.len()];
+    let mut missing = vec![];
+    {
+        let cache = EMBEDDINGS.lock().await;
+        for (i, input) in inputs.iter().enumerate() {
+            match cache.get(input) {
+                Some(v) => {
+                    EMBEDDING_CACHE.with_label_values(&["hit"]).inc();
+                    out[i] = v.clone();
+                }
+                None => {
+                    EMBEDDING_CACHE.with_label_values(&["miss"]).inc();
+                    missing.push(i);
+                }
+            }
+        }
+    }
+    if missing.is_empty() {
+        return Ok(out);
+    }
+    let batch: Vec<String> = missing.iter().map(|&i| inputs[i].clone()).collect();
+    let fetched = fetch_embeddings(&batch).await?;
+    let mut cache = EMBEDDINGS.lock().await;
+    if cache.len() + fetched.len() > EMBEDDING_CACHE_SIZE {
+        cache.clear();
+    }
+    for (i, v) in missing.into_iter().zip(fetched) {
+        cache.insert(inputs[i].clone(), v.clone());
+        out[i] = v;
+    }
+    Ok(out)
+}
+
+/// Calls the embedding service directly, skipping the cache.
+pub async fn fetch_embedding(query: &str) -> AppResult<serde_json::Value> {
+    Ok(fetch_embeddings(&[query.to_string()])
+        .await?
+        .swap_remove(0))
+}
+
+/// Embeds `inputs` in one call to the embedding service, skipping the cache.
+/// Results come back in input order.
+pub async fn fetch_embeddings(inputs: &[String]) -> AppResult<Vec<serde_json::Value>> {
     let url = SECRETS
         .lock()
         .await
         .get("EMBEDDING_URL")
         .ok_or("QDRANT_KEY not found in env")
-        .map_err(|e| AppError::new_plain(e))?;
-    Ok(reqwest::Client::new()
-        .post(&url)
-        .json(&json!({ "input": query }))
-        .send()
-        .await
-        .map_err(|e| AppError::new("sending get_embedding request", e))?
-        .json::<serde_json::Value>()
-        .await
-        .map_err(|e| AppError::new("parsing get_embedding response to json", e))?["data"][0]
-        ["embedding"]
-        .clone())
-}
\ No newline at end of file
+        .map_err(AppError::new_plain)?;
+    EMBEDDING_BATCH_SIZE.observe(inputs.len() as f64);
+    // a single input is sent bare, as it always has been
+    let input = match inputs {
+        [one] => json!(one),
+        _ => json!(inputs),
+    };
+    let start = Instant::now();
+    let res = call(&EMBEDDING_BREAKER, EMBEDDING_POLICY, || async {
+        let res = reqwest::Client::new()
+            .post(&url)
+            .json(&json!({ "input": input }))
+            .send()
+            .await
+            .map_err(|e| Failure::from_reqwest("sending get_embedding request", e))?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(Failure::from_status("embedding", status, &body));
+        }
+        res.json::<serde_json::Value>().await.map_err(|e| {
+            Failure::permanent(AppError::new("parsing get_embedding response to json", e))
+        })
+    })
+    .instrument(tracing::info_span!("embedding", batch = inputs.len()))
+    .await;
+    EMBEDDING_LATENCY.observe(start.elapsed().as_secs_f64());
+    let res = res?;
+    let mut data: Vec<serde_json::Value> = res["data"].as_array().cloned().unwrap_or_default();
+    // OpenAI-style responses carry an index; don't rely on the order
+    data.sort_by_key(|d| d["index"].as_u64().unwrap_or(0));
+    if data.len() != inputs.len() {
+        return Err(AppError::new_plain(&format!(
+            "embedding service returned {} vectors for {} inputs",
+            data.len(),
+            inputs.len()
+        )));
+    }
+    Ok(data.into_iter().map(|d| d["embedding"].clone()).collect())
+}
+
+/// Longest line, in bytes, `ndjson_lines` will buffer.
+pub const MAX_LINE: usize = 1 << 20;
+
+/// Splits a request body into lines of newline-delimited JSON without
+/// buffering more than one line at a time. Every line is yielded, blank ones
+/// included, so callers can number lines as they appear in the body. A line
+/// over `MAX_LINE` is yielded as an inner error and skipped; an outer error
+/// means the body could not be read, and ends the stream.
+pub fn ndjson_lines<S, B>(body: S) -> impl Stream<Item = AppResult<Result<String, String>>>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: warp::Buf,
+{
+    stream::unfold(
+        (Box::pin(body), Vec::<u8>::new(), false, false),
+        |(mut body, mut buf, mut done, mut skipping)| async move {
+            let too_long = || Err(format!("line longer than {} bytes", MAX_LINE));
+            loop {
+                if let Some(i) = buf.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = buf.drain(..=i).collect();
+                    if std::mem::take(&mut skipping) {
+                        // the rest of a line already reported as too long
+                        continue;
+                    }
+                    let line = if i > MAX_LINE {
+                        too_long()
+                    } else {
+                        Ok(String::from_utf8_lossy(&line).trim().to_string())
+                    };
+                    return Some((Ok(line), (body, buf, done, skipping)));
+                }
+                if buf.len() > MAX_LINE && !skipping {
+                    buf.clear();
+                    skipping = true;
+                    return Some((Ok(too_long()), (body, buf, done, skipping)));
+                }
+                if skipping {
+                    buf.clear();
+                }
+                if done {
+                    if skipping || buf.iter().all(u8::is_ascii_whitespace) {
+                        return None;
+                    }
+                    let line = String::from_utf8_lossy(&buf).trim().to_string();
+                    buf.clear();
+                    return Some((Ok(Ok(line)), (body, buf, done, skipping)));
+                }
+                match body.next().await {
+                    Some(Ok(mut chunk)) => {
+                        while chunk.has_remaining() {
+                            let c = chunk.chunk();
+                            buf.extend_from_slice(c);
+                            let n = c.len();
+                            chunk.advance(n);
+                        }
+                    }
+                    Some(Err(e)) => {
+                        done = true;
+                        buf.clear();
+                        return Some((
+                            Err(AppError::new("reading request body", e)),
+                            (body, buf, done, skipping),
+                        ));
+                    }
+                    None => done = true,
+                }
+            }
+        },
+    )
+}
@@ -0,0 +1,346 @@
+//! Visitor identity across chats. A visitor is named by a `visitor` cookie
+//! signed with the `VISITOR_KEY` secret; widgets that can't rely on
+//! third-party cookies send the same signed token back in an `X-Visitor-Id`
+//! header, which only counts when the cookie is missing or invalid. Failing
+//! both, a new ID is minted and its token handed back in both. The ID is
+//! stored as `v` on every message. Without `VISITOR_KEY` no visitor is
+//! recorded at all, as no ID could be trusted.
+//!
+//! Merged visitors live on as aliases in the vectorless `visitors`
+//! collection, so messages still arriving under the old ID land on the new
+//! one.
+
+use std::{collections::BTreeMap, convert::Infallible};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use warp::{
+    http::header::SET_COOKIE,
+    reply::{Reply, Response},
+    Filter, Rejection,
+};
+
+use crate::{
+    app::{AppError, AppResult},
+    auth::same,
+    constants::{COLLECTION, SECRETS, VISITOR_COLLECTION},
+    privacy::hmac_hex,
+    qdrant::{qdrant_path, qdrant_post, qdrant_put, vectorless_collection},
+    store,
+    transcript::timestamp,
+};
+
+pub const VISITOR_HEADER: &str = "x-visitor-id";
+pub const VISITOR_COOKIE: &str = "visitor";
+const MAX_LEN: usize = 128;
+/// Alias hops followed before giving up, in case merges formed a cycle.
+const MAX_HOPS: usize = 8;
+const PAGE: usize = 256;
+
+#[cfg(test)]
+mod tests;
+
+/// The visitor behind a request.
+pub struct Visitor {
+    /// None when `VISITOR_KEY` isn't configured.
+    pub id: Option<String>,
+    /// Signed token naming the visitor, returned in `X-Visitor-Id`.
+    token: Option<String>,
+    /// `Set-Cookie` value to send when the visitor had no valid cookie.
+    cookie: Option<String>,
+}
+
+fn valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+}
+
+fn signed(key: &str, id: &str) -> String {
+    format!("{}.{}", id, hmac_hex(key, id))
+}
+
+fn verify(key: &str, token: &str) -> Option<String> {
+    let (id, _) = token.trim().rsplit_once('.')?;
+    (valid(id) && same(signed(key, id).as_bytes(), token.trim().as_bytes())).then(|| id.to_string())
+}
+
+async fn identify(header: Option<String>, cookie: Option<String>) -> Result<Visitor, Infallible> {
+    let Some(key) = SECRETS
+        .lock()
+        .await
+        .get("VISITOR_KEY")
+        .filter(|k| !k.is_empty())
+    else {
+        return Ok(Visitor {
+            id: None,
+            token: None,
+            cookie: None,
+        });
+    };
+    if let Some(id) = cookie.as_deref().and_then(|c| verify(&key, c)) {
+        return Ok(Visitor {
+            token: Some(signed(&key, &id)),
+            id: Some(id),
+            cookie: None,
+        });
+    }
+    let id = header
+        .as_deref()
+        .and_then(|h| verify(&key, h))
+        .unwrap_or_else(|| uuid::Uuid::now_v7().to_string());
+    let token = signed(&key, &id);
+    // a header-named visitor whose cookie was lost gets it back
+    let cookie = format!(
+        "{}={}; Path=/; Max-Age=31536000; HttpOnly; Secure; SameSite=None",
+        VISITOR_COOKIE, token
+    );
+    Ok(Visitor {
+        id: Some(id),
+        token: Some(token),
+        cookie: Some(cookie),
+    })
+}
+
+/// Extracts the visitor of the request, minting one if needed.
+pub fn visitor() -> impl Filter<Extract = (Visitor,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(VISITOR_HEADER)
+        .and(warp::cookie::optional::<String>(VISITOR_COOKIE))
+        .and_then(identify)
+}
+
+impl Visitor {
+    /// Adds the visitor's token, and a fresh cookie if it had none, to
+    /// `reply`.
+    pub fn reply(&self, reply: impl Reply) -> Response {
+        let mut res = reply.into_response();
+        if let Some(t) = self.token.as_ref().and_then(|t| t.parse().ok()) {
+            res.headers_mut().insert(VISITOR_HEADER, t);
+        }
+        if let Some(c) = self.cookie.as_ref().and_then(|c| c.parse().ok()) {
+            res.headers_mut().insert(SET_COOKIE, c);
+        }
+        res
+    }
+}
+
+fn alias_id(visitor: &str) -> String {
+    uuid::Uuid::new_v5(
+        &uuid::Uuid::NAMESPACE_URL,
+        format!("qdrant-warp:visitor:{}", visitor).as_bytes(),
+    )
+    .to_string()
+}
+
+/// The ID `visitor` was merged into, or `visitor` itself.
+pub async fn resolve(visitor: &str) -> AppResult<String> {
+    vectorless_collection(VISITOR_COLLECTION).await?;
+    let mut id = visitor.to_string();
+    for _ in 0..MAX_HOPS {
+        let res = qdrant_post(
+            &qdrant_path(&format!("collections/{}/points", VISITOR_COLLECTION)).await?,
+            json!({"ids": [alias_id(&id)], "with_payload": ["merged_into"]}),
+        )
+        .await?;
+        match res["result"][0]["payload"]["merged_into"].as_str() {
+            Some(into) => id = into.to_string(),
+            None => break,
+        }
+    }
+    Ok(id)
+}
+
+/// `visitor`, the ID it was merged into and every ID merged into that one:
+/// all the IDs the visitor's messages may carry.
+pub async fn family(visitor: &str) -> AppResult<Vec<String>> {
+    let root = resolve(visitor).await?;
+    let mut ids = vec![visitor.to_string(), root.clone()];
+    let mut offset = Value::Null;
+    loop {
+        let mut body = json!({
+            "limit": PAGE,
+            "with_payload": ["v"],
+            "filter": {"must": [{"key": "merged_into", "match": {"value": root}}]},
+        });
+        if !offset.is_null() {
+            body["offset"] = offset;
+        }
+        let res = qdrant_post(
+            &qdrant_path(&format!("collections/{}/points/scroll", VISITOR_COLLECTION)).await?,
+            body,
+        )
+        .await?;
+        ids.extend(
+            res["result"]["points"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|p| p["payload"]["v"].as_str().map(str::to_string)),
+        );
+        offset = res["result"]["next_page_offset"].clone();
+        if offset.is_null() {
+            break;
+        }
+    }
+    ids.sort_unstable();
+    ids.dedup();
+    Ok(ids)
+}
+
+/// Deletes the alias records of `ids` and those merged into them.
+pub async fn forget(ids: &[String]) -> AppResult<()> {
+    vectorless_collection(VISITOR_COLLECTION).await?;
+    qdrant_post(
+        &qdrant_path(&format!(
+            "collections/{}/points/delete?wait=true",
+            VISITOR_COLLECTION
+        ))
+        .await?,
+        json!({"filter": {"should": [
+            {"key": "v", "match": {"any": ids}},
+            {"key": "merged_into", "match": {"any": ids}},
+        ]}}),
+    )
+    .await?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ChatSummary {
+    pub chat: String,
+    pub messages: usize,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+    pub visitor: String,
+    pub messages: usize,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub chats: Vec<ChatSummary>,
+}
+
+/// Every chat of `visitor`, most recent first.
+pub async fn summary(visitor: &str) -> AppResult<Summary> {
+    let visitor = resolve(visitor).await?;
+    let mut chats: BTreeMap<String, ChatSummary> = BTreeMap::new();
+    let mut offset = Value::Null;
+    loop {
+        let mut body = json!({
+            "limit": PAGE,
+            "with_payload": ["i", "d"],
+            "filter": {"must": [{"key": "v", "match": {"value": visitor}}]},
+        });
+        if !offset.is_null() {
+            body["offset"] = offset;
+        }
+        let res = qdrant_post(
+            &qdrant_path(&format!("collections/{}/points/scroll", COLLECTION)).await?,
+            body,
+        )
+        .await?;
+        for p in res["result"]["points"].as_array().into_iter().flatten() {
+            let Some(chat) = p["payload"]["i"].as_str() else {
+                continue;
+            };
+            let time = timestamp(&p["payload"]["d"]);
+            let c = chats
+                .entry(chat.to_string())
+                .or_insert_with(|| ChatSummary {
+                    chat: chat.to_string(),
+                    messages: 0,
+                    first_seen: None,
+                    last_seen: None,
+                });
+            c.messages += 1;
+            c.first_seen = match (c.first_seen, time) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            c.last_seen = c.last_seen.max(time);
+        }
+        offset = res["result"]["next_page_offset"].clone();
+        if offset.is_null() {
+            break;
+        }
+    }
+    let mut chats: Vec<ChatSummary> = chats.into_values().collect();
+    chats.sort_by_key(|c| std::cmp::Reverse(c.last_seen));
+    Ok(Summary {
+        visitor,
+        messages: chats.iter().map(|c| c.messages).sum(),
+        first_seen: chats.iter().filter_map(|c| c.first_seen).min(),
+        last_seen: chats.iter().filter_map(|c| c.last_seen).max(),
+        chats,
+    })
+}
+
+#[derive(Serialize)]
+pub struct Merge {
+    pub from: String,
+    pub into: String,
+    /// Messages moved over to `into`.
+    pub moved: u64,
+}
+
+/// Makes `from` part of `into`: its messages are relabeled and the ID stays
+/// behind as an alias, as do IDs merged into `from` earlier.
+pub async fn merge(from: &str, into: &str) -> AppResult<Merge> {
+    let into = resolve(into).await?;
+    if !valid(from) || from == into {
+        return Err(AppError::new_plain("cannot merge a visitor into itself"));
+    }
+    let filter = json!({"must": [{"key": "v", "match": {"value": from}}]});
+    let moved = qdrant_post(
+        &qdrant_path(&format!("collections/{}/points/count", COLLECTION)).await?,
+        json!({"filter": filter, "exact": true}),
+    )
+    .await?["result"]["count"]
+        .as_u64()
+        .unwrap_or(0);
+    qdrant_post(
+        &qdrant_path(&format!(
+            "collections/{}/points/payload?wait=true",
+            COLLECTION
+        ))
+        .await?,
+        json!({"payload": {"v": into}, "filter": filter}),
+    )
+    .await?;
+    qdrant_post(
+        &qdrant_path(&format!(
+            "collections/{}/points/payload?wait=true",
+            VISITOR_COLLECTION
+        ))
+        .await?,
+        json!({
+            "payload": {"merged_into": into},
+            "filter": {"must": [{"key": "merged_into", "match": {"value": from}}]},
+        }),
+    )
+    .await?;
+    qdrant_put(
+        &qdrant_path(&format!(
+            "collections/{}/points?wait=true",
+            VISITOR_COLLECTION
+        ))
+        .await?,
+        json!({"points": [{
+            "id": alias_id(from),
+            "vector": {},
+            "payload": {"v": from, "merged_into": into, "d": store::now()},
+        }]}),
+    )
+    .await?;
+    tracing::info!(moved, "visitors merged");
+    Ok(Merge {
+        from: from.to_string(),
+        into,
+        moved,
+    })
+}
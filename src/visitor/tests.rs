@@ -0,0 +1,20 @@
+use super::{signed, verify};
+
+#[test]
+fn tokens_name_their_visitor() {
+    let token = signed("key", "0190-abc");
+    assert_eq!(verify("key", &token), Some("0190-abc".to_string()));
+    assert_eq!(
+        verify("key", &format!(" {} ", token)),
+        Some("0190-abc".to_string())
+    );
+}
+
+#[test]
+fn forged_tokens_name_nobody() {
+    let token = signed("key", "0190-abc");
+    assert_eq!(verify("other", &token), None);
+    assert_eq!(verify("key", &token.replace("0190-abc", "0190-abd")), None);
+    assert_eq!(verify("key", "0190-abc"), None);
+    assert_eq!(verify("key", &signed("key", "a b")), None);
+}